@@ -0,0 +1,250 @@
+use crate::{
+    Message,
+    diagnostics::Diagnostic,
+    events::{self, Lifecycle},
+    output::{self, log},
+    process::{self, Signal},
+    testing::TestResult,
+    tui::{self, Event, Pane},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Read},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+/// What happens to a running build when new changes arrive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildPolicy {
+    /// Kill the running build and start over immediately
+    #[default]
+    Restart,
+    /// Let the running build finish, then rebuild
+    Queue,
+}
+
+/// A quick command like `cargo check` or `cargo clippy` that has to pass
+/// before the build command runs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Check {
+    pub cmd:           Vec<String>,
+    /// Also treat warnings as a failed check
+    #[serde(default)]
+    pub deny_warnings: bool,
+}
+
+/// What came out of a build
+pub struct Outcome {
+    /// The paths of the executable build artifacts, or why the build failed
+    pub result:      eyre::Result<Vec<PathBuf>>,
+    /// Diagnostics from the compiler, without duplicates
+    pub diagnostics: Vec<Diagnostic>,
+    /// Results of the tests, if the command ran any
+    pub tests:       Vec<TestResult>,
+    /// What cargo itself printed to stderr
+    pub stderr:      String,
+    pub duration:    Duration,
+}
+
+/// The command of a task running in the background
+pub struct Build {
+    pub id:   u64,
+    pub task: String,
+    pid:      u32,
+}
+
+impl Build {
+    /// Start `cmd` for `task` in its own process group. Its [`Outcome`] is
+    /// sent to `tx` as a [`Message::Built`] with the given `id`. With `json`,
+    /// the output of `cmd` is parsed as cargo's JSON messages, otherwise it's
+    /// shown tagged with `task`.
+    pub fn spawn(
+        mut cmd: Command,
+        task: &str,
+        json: bool,
+        id: u64,
+        tx: Sender<Message>,
+    ) -> eyre::Result<Self> {
+        let description = format!("{cmd:?}");
+        let dir = cmd.get_current_dir().map(Path::to_path_buf);
+        log!("Running task {task:?}: {description}");
+        let started = Instant::now();
+        // The task would be stopped by SIGTTIN if it read from the terminal
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = cmd.process_group(0).spawn()?;
+        if !json {
+            output::forward(&mut child, task, Pane::Tasks, None);
+        }
+        let pid = child.id();
+        events::emit(Lifecycle::TaskStarted {
+            task: task.to_string(),
+            command: description.clone(),
+            pid,
+        });
+
+        std::thread::spawn(move || {
+            let outcome = wait(child, started, dir.as_deref());
+            if outcome.result.is_ok() {
+                log!("Done running: {description}");
+            }
+            // The main loop only goes away when watchf exits
+            let _ = tx.send(Message::Built { id, outcome });
+        });
+        Ok(Self {
+            id,
+            task: task.to_string(),
+            pid,
+        })
+    }
+
+    /// Kill the command along with everything it spawned
+    pub fn cancel(self) {
+        log!("Cancelling task {:?} with pid {}...", self.task, self.pid);
+        if let Err(e) = process::signal_group(self.pid, Signal::KILL) {
+            log!("Failed to kill task with pid {}: {e}", self.pid);
+        }
+        events::emit(Lifecycle::TaskCancelled {
+            task: self.task.clone(),
+        });
+        tui::send(Event::TaskCancelled { name: self.task });
+    }
+}
+
+/// Wait for the build running in `dir` to finish while collecting the
+/// executable build artifacts and compiler diagnostics from cargo's JSON
+/// messages
+fn wait(mut child: Child, started: Instant, dir: Option<&Path>) -> Outcome {
+    #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+    pub struct CompilerArtifact {
+        pub reason:        String,
+        pub package_id:    String,
+        pub manifest_path: String,
+        pub target:        Target,
+        pub features:      Vec<String>,
+        pub filenames:     Vec<String>,
+        pub executable:    Option<PathBuf>,
+        pub fresh:         bool,
+    }
+
+    #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+    pub struct Target {
+        pub kind:        Vec<String>,
+        pub crate_types: Vec<String>,
+        pub name:        String,
+        pub src_path:    String,
+        pub edition:     String,
+        pub doc:         bool,
+        pub doctest:     bool,
+        pub test:        bool,
+    }
+
+    // Read stderr on the side so neither pipe can fill up and block cargo
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = stderr.read_to_string(&mut buf);
+            buf
+        })
+    });
+
+    let mut artifacts = Vec::new();
+    let mut diagnostics = Vec::<Diagnostic>::new();
+    let mut tests = Vec::new();
+    // libtest's output when it can't report its results as JSON
+    let mut plain = Vec::new();
+    let stdout = child.stdout.take().map(BufReader::new);
+    for line in stdout
+        .into_iter()
+        .flat_map(BufRead::lines)
+        .map_while(Result::ok)
+    {
+        let Ok(mut message) = serde_json::from_str::<Value>(&line) else {
+            plain.push(line);
+            continue;
+        };
+        let reason = message["reason"].as_str().unwrap_or_default().to_string();
+        match reason.as_str() {
+            "compiler-artifact" => {
+                if let Ok(artifact) = serde_json::from_value::<CompilerArtifact>(message)
+                    && artifact.target.kind.iter().any(|x| x == "bin")
+                    && let Some(executable) = artifact.executable
+                {
+                    artifacts.push(executable);
+                }
+            }
+            "compiler-message" => {
+                // The same diagnostic is reported for each target it affects
+                if let Ok(diagnostic) =
+                    serde_json::from_value::<Diagnostic>(message["message"].take())
+                    && !diagnostics
+                        .iter()
+                        .any(|d| d.rendered == diagnostic.rendered)
+                {
+                    diagnostics.push(diagnostic);
+                }
+            }
+            // Not a message from cargo, but from a test binary
+            "" => tests.extend(TestResult::parse(message)),
+            _ => {}
+        }
+    }
+
+    if tests.is_empty() {
+        tests = TestResult::parse_plain(plain.iter().map(String::as_str));
+    }
+    if !diagnostics.is_empty() {
+        let root = workspace_root(dir);
+        for diagnostic in &mut diagnostics {
+            diagnostic.resolve(&root);
+        }
+    }
+
+    let result = child
+        .wait()
+        .map_err(eyre::Report::from)
+        .and_then(|status| Ok(status.exit_ok()?))
+        .map(|()| artifacts);
+    Outcome {
+        result,
+        diagnostics,
+        tests,
+        stderr: stderr.and_then(|h| h.join().ok()).unwrap_or_default(),
+        duration: started.elapsed(),
+    }
+}
+
+/// The root of the cargo workspace `dir` belongs to, which the paths in
+/// diagnostics are relative to. Falls back to `dir` itself.
+fn workspace_root(dir: Option<&Path>) -> PathBuf {
+    let dir = match dir {
+        Some(dir) => std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf()),
+        None => std::env::current_dir().unwrap_or_default(),
+    };
+    let manifest = Command::new("cargo")
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    match manifest
+        .as_deref()
+        .map(str::trim)
+        .map(Path::new)
+        .and_then(Path::parent)
+    {
+        Some(root) => root.to_path_buf(),
+        None => dir,
+    }
+}
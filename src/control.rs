@@ -0,0 +1,232 @@
+use crate::{
+    Message,
+    output::{self, Line, log},
+};
+use eyre::{WrapErr, bail, eyre};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+/// Where the control socket is created unless configured otherwise
+pub fn default_socket() -> PathBuf {
+    PathBuf::from("target/watchf/control.sock")
+}
+
+/// What a client asks a running watchf to do, sent as one line of JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Report the outcome of the latest run of each task
+    Status,
+    /// Run `tasks` and everything depending on them, or everything if empty
+    Trigger { tasks: Vec<String> },
+    /// Restart `services`, or all of them if empty
+    Restart { services: Vec<String> },
+    /// Stop reacting to changes
+    Pause,
+    /// React to changes again
+    Resume,
+    /// Stream every line of output from now on
+    Logs,
+}
+
+/// The answer to a [`Request`], sent as one line of JSON. [`Request::Logs`]
+/// is answered with one [`Response::Line`] per line of output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    Error { message: String },
+    Status(Status),
+    Line(Line),
+}
+
+/// The state of a running watchf
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    pub paused:   bool,
+    pub tasks:    BTreeMap<String, TaskStatus>,
+    /// The supervised services and what they're up to
+    pub services: BTreeMap<String, ServiceStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum TaskStatus {
+    Running,
+    Finished {
+        ok:          bool,
+        duration_ms: u64,
+        errors:      usize,
+        warnings:    usize,
+    },
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum ServiceStatus {
+    /// Restarted, but not up or ready yet
+    Starting,
+    Running,
+    /// Running, but its readiness check failed
+    NotReady {
+        error: String,
+    },
+    /// Not running until it's (re)started
+    Stopped,
+    /// Exited on its own and is restarted after a backoff
+    Crashed {
+        status: String,
+    },
+    /// Exited on its own and stays down until the next change
+    Exited {
+        status: String,
+    },
+    /// Exited too many times in a row to be restarted again
+    GaveUp {
+        status: String,
+    },
+    /// Couldn't be started
+    Failed {
+        error: String,
+    },
+}
+
+/// Listen for clients on the Unix socket at `path`. Their requests are sent to
+/// `tx` as [`Message::Control`], except for [`Request::Logs`].
+pub fn serve(path: &Path, tx: Sender<Message>) -> eyre::Result<()> {
+    // A socket that nobody accepts connections on is left over from before
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!("Another watchf is listening on {}", path.display());
+        }
+        fs::remove_file(path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(path)
+        .wrap_err_with(|| format!("Failed to listen on {}", path.display()))?;
+    thread::spawn(move || {
+        for client in listener.incoming().map_while(Result::ok) {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                if let Err(e) = answer(client, &tx) {
+                    log!("Control socket: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Answer the requests of a single client until it disconnects
+fn answer(client: UnixStream, tx: &Sender<Message>) -> eyre::Result<()> {
+    let mut writer = client.try_clone()?;
+    let mut send = |response: &Response| -> eyre::Result<()> {
+        let mut json = serde_json::to_string(response)?;
+        json.push('\n');
+        Ok(writer.write_all(json.as_bytes())?)
+    };
+    for line in BufReader::new(client).lines() {
+        let request = match serde_json::from_str::<Request>(&line?) {
+            Ok(request) => request,
+            Err(e) => {
+                send(&Response::Error {
+                    message: e.to_string(),
+                })?;
+                continue;
+            }
+        };
+        if let Request::Logs = request {
+            // Until the client goes away
+            for line in output::subscribe() {
+                if send(&Response::Line(line)).is_err() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        let (reply_tx, reply_rx) = mpsc::channel();
+        tx.send(Message::Control {
+            request,
+            reply: reply_tx,
+        })
+        .map_err(|_| eyre!("watchf is shutting down"))?;
+        send(&reply_rx.recv()?)?;
+    }
+    Ok(())
+}
+
+/// Send `request` to the watchf listening on `path` and print its answers
+pub fn request(path: &Path, request: &Request) -> eyre::Result<()> {
+    let mut stream = UnixStream::connect(path).wrap_err_with(|| {
+        format!(
+            "Failed to connect to watchf at {}, is it running?",
+            path.display()
+        )
+    })?;
+    let mut json = serde_json::to_string(request)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+
+    for line in BufReader::new(stream).lines() {
+        match serde_json::from_str::<Response>(&line?)? {
+            Response::Ok => return Ok(()),
+            Response::Error { message } => bail!(message),
+            Response::Status(status) => {
+                print_status(&status);
+                return Ok(());
+            }
+            Response::Line(line) => println!("{} | {}", line.tag, line.text),
+        }
+    }
+    Ok(())
+}
+
+fn print_status(status: &Status) {
+    if status.paused {
+        println!("Paused");
+    }
+    for (name, task) in &status.tasks {
+        let state = match task {
+            TaskStatus::Running => "running".to_string(),
+            TaskStatus::Cancelled => "cancelled".to_string(),
+            TaskStatus::Finished {
+                ok,
+                duration_ms,
+                errors,
+                warnings,
+            } => format!(
+                "{} in {:.2}s, {errors} error(s), {warnings} warning(s)",
+                if *ok { "succeeded" } else { "failed" },
+                *duration_ms as f64 / 1000.0,
+            ),
+        };
+        println!("task {name}: {state}");
+    }
+    for (name, service) in &status.services {
+        let state = match service {
+            ServiceStatus::Starting => "starting".to_string(),
+            ServiceStatus::Running => "running".to_string(),
+            ServiceStatus::NotReady { error } => format!("running, not ready: {error}"),
+            ServiceStatus::Stopped => "stopped".to_string(),
+            ServiceStatus::Crashed { status } => format!("restarting, it {status}"),
+            ServiceStatus::Exited { status } => format!("stopped, it {status}"),
+            ServiceStatus::GaveUp { status } => {
+                format!("given up on, it {status} too many times in a row")
+            }
+            ServiceStatus::Failed { error } => format!("failed to start: {error}"),
+        };
+        println!("service {name}: {state}");
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// The number of paths that are listed when a change set is displayed
+const DISPLAYED_PATHS: usize = 8;
+
+/// Settings for batching filesystem events into change sets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Debounce {
+    /// Milliseconds without any new events after which a batch is complete
+    pub quiet_ms:    u64,
+    /// Maximum milliseconds to wait for a quiet period after the first event
+    /// of a batch
+    pub max_wait_ms: u64,
+}
+
+impl Default for Debounce {
+    fn default() -> Self {
+        Self {
+            quiet_ms:    100,
+            max_wait_ms: 1000,
+        }
+    }
+}
+
+/// A deduplicated batch of paths that changed since the last build
+#[derive(Debug, Default)]
+pub struct ChangeSet {
+    paths: BTreeSet<PathBuf>,
+    /// When the first and the latest path were added
+    first: Option<Instant>,
+    last:  Option<Instant>,
+}
+
+impl ChangeSet {
+    pub fn insert(&mut self, path: PathBuf) {
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.paths.insert(path);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.paths.iter().map(PathBuf::as_path)
+    }
+
+    /// When this batch is complete, or `None` if it's empty
+    pub fn deadline(&self, debounce: &Debounce) -> Option<Instant> {
+        let first = self.first? + Duration::from_millis(debounce.max_wait_ms);
+        let last = self.last? + Duration::from_millis(debounce.quiet_ms);
+        Some(first.min(last))
+    }
+
+    /// Take the batch if it's complete, leaving an empty one behind
+    pub fn take_ready(&mut self, debounce: &Debounce) -> Option<ChangeSet> {
+        self.deadline(debounce)
+            .is_some_and(|deadline| deadline <= Instant::now())
+            .then(|| std::mem::take(self))
+    }
+}
+
+impl fmt::Display for ChangeSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        for (i, path) in self.paths.iter().take(DISPLAYED_PATHS).enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let path = path.strip_prefix(&cwd).unwrap_or(path);
+            write!(f, "{}", path.display())?;
+        }
+        if self.paths.len() > DISPLAYED_PATHS {
+            write!(f, " (+{} more)", self.paths.len() - DISPLAYED_PATHS)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debounce(quiet_ms: u64, max_wait_ms: u64) -> Debounce {
+        Debounce {
+            quiet_ms,
+            max_wait_ms,
+        }
+    }
+
+    #[test]
+    fn empty_batches_are_never_ready() {
+        let mut changes = ChangeSet::default();
+        assert!(changes.deadline(&debounce(0, 0)).is_none());
+        assert!(changes.take_ready(&debounce(0, 0)).is_none());
+    }
+
+    #[test]
+    fn takes_complete_batches() {
+        let mut changes = ChangeSet::default();
+        changes.insert(PathBuf::from("src/main.rs"));
+        changes.insert(PathBuf::from("src/main.rs"));
+        changes.insert(PathBuf::from("src/lib.rs"));
+        assert!(changes.take_ready(&debounce(60_000, 60_000)).is_none());
+
+        let batch = changes.take_ready(&debounce(0, 0)).unwrap();
+        assert_eq!(batch.paths().count(), 2);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn max_wait_cuts_the_quiet_period_short() {
+        let mut changes = ChangeSet::default();
+        changes.insert(PathBuf::from("src/main.rs"));
+        assert!(changes.take_ready(&debounce(60_000, 0)).is_some());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::output::paint;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Settings for how compiler diagnostics are shown
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Diagnostics {
+    pub style:            Style,
+    /// Turn locations into terminal hyperlinks. Either `"file"`, `"vscode"` or
+    /// a template using `{path}`, `{line}` and `{column}`.
+    pub hyperlink_format: Option<String>,
+    /// File that receives the diagnostics of the latest build in a layout
+    /// vim's default `errorformat` understands
+    pub errorfile:        Option<PathBuf>,
+    /// File that receives the diagnostics of the latest build as a JSON list
+    pub errorfile_json:   Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Style {
+    /// The full message from the compiler, including the code snippets
+    #[default]
+    Full,
+    /// One line per diagnostic
+    Short,
+}
+
+/// Severity of a diagnostic, ordered from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    /// Also internal compiler errors, which are reported as failures too
+    #[serde(alias = "error: internal compiler error")]
+    Error,
+    Warning,
+    Note,
+    Help,
+    /// Remarks like "For more information about this error, try `rustc
+    /// --explain E0308`"
+    FailureNote,
+    #[serde(other)]
+    Other,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Note => "note",
+            Level::Help => "help",
+            Level::FailureNote => "failure-note",
+            Level::Other => "other",
+        }
+    }
+}
+
+/// A diagnostic from a `compiler-message` emitted by cargo
+#[derive(Debug, Clone, Deserialize)]
+pub struct Diagnostic {
+    pub message:  String,
+    pub code:     Option<Code>,
+    pub level:    Level,
+    pub spans:    Vec<Span>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Code {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Span {
+    /// Relative to the workspace root
+    pub file_name:    PathBuf,
+    /// `file_name` resolved against the workspace root, see
+    /// [`Diagnostic::resolve`]
+    #[serde(skip)]
+    pub path:         PathBuf,
+    pub line_start:   usize,
+    pub column_start: usize,
+    pub is_primary:   bool,
+}
+
+impl Diagnostic {
+    pub fn primary_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|s| s.is_primary)
+    }
+
+    /// Resolve the paths of the spans against the workspace `root` the
+    /// compiler ran in
+    pub fn resolve(&mut self, root: &Path) {
+        for span in &mut self.spans {
+            span.path = root.join(&span.file_name);
+        }
+    }
+
+    /// Whether this is one of rustc's closing remarks, like "aborting due to 2
+    /// previous errors", "1 warning emitted" or a pointer to `rustc --explain`,
+    /// rather than an actual problem
+    pub fn is_summary(&self) -> bool {
+        self.spans.is_empty()
+            && (self.level == Level::FailureNote
+                || self.message.starts_with("aborting due to")
+                || self.message.ends_with("emitted"))
+    }
+}
+
+#[cfg(test)]
+impl Diagnostic {
+    /// A diagnostic as cargo reports it, at line 3, column 5 of `file` if
+    /// there is one
+    pub fn new(level: &str, message: &str, file: Option<&str>) -> Self {
+        let spans = file.map(|file| {
+            serde_json::json!({
+                "file_name": file,
+                "line_start": 3,
+                "column_start": 5,
+                "is_primary": true,
+            })
+        });
+        serde_json::from_value(serde_json::json!({
+            "message": message,
+            "code": null,
+            "level": level,
+            "spans": Vec::from_iter(spans),
+            "rendered": null,
+        }))
+        .unwrap()
+    }
+}
+
+/// The number of errors and warnings among some diagnostics
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub errors:   usize,
+    pub warnings: usize,
+}
+
+impl Counts {
+    pub fn of<'a>(diagnostics: impl IntoIterator<Item = &'a Diagnostic>) -> Self {
+        let mut counts = Self::default();
+        for diagnostic in diagnostics.into_iter().filter(|d| !d.is_summary()) {
+            match diagnostic.level {
+                Level::Error => counts.errors += 1,
+                Level::Warning => counts.warnings += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+}
+
+/// Render `diagnostics` grouped by file, with files containing errors first and
+/// errors before warnings within each file
+pub fn render(diagnostics: &[Diagnostic], config: &Diagnostics) -> String {
+    let color = std::io::stderr().is_terminal();
+    let mut files = BTreeMap::<Option<(&Path, &Path)>, Vec<&Diagnostic>>::new();
+    for diagnostic in diagnostics.iter().filter(|d| !d.is_summary()) {
+        let file = diagnostic
+            .primary_span()
+            .map(|s| (s.file_name.as_path(), s.path.as_path()));
+        files.entry(file).or_default().push(diagnostic);
+    }
+    for diagnostics in files.values_mut() {
+        diagnostics.sort_by_key(|d| (d.level, d.primary_span().map(|s| s.line_start)));
+    }
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by_key(|(_, diagnostics)| diagnostics.first().map(|d| d.level));
+
+    let mut out = String::new();
+    for (file, diagnostics) in files {
+        let counts = Counts::of(diagnostics.iter().copied());
+        let name = match file {
+            Some((file, path)) => link(config, path, None, &file.display().to_string()),
+            None => "(no location)".to_string(),
+        };
+        let _ = writeln!(out, "{} ({})", paint(color, "1", &name), counts);
+
+        for diagnostic in diagnostics {
+            match (config.style, &diagnostic.rendered) {
+                (Style::Full, Some(rendered)) => {
+                    let _ =
+                        writeln!(out, "{}", link_rendered(config, diagnostic, rendered));
+                }
+                _ => {
+                    let _ = writeln!(out, "  {}", short(config, color, diagnostic));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// A one-line summary of a finished task
+pub fn summary(task: &str, failed: bool, counts: Counts, duration: Duration) -> String {
+    let color = std::io::stderr().is_terminal();
+    let status = if failed {
+        paint(color, "1;31", &format!("{task} failed"))
+    } else {
+        paint(color, "1;32", &format!("{task} finished"))
+    };
+    format!("{status}: {counts} in {:.2}s", duration.as_secs_f64())
+}
+
+impl std::fmt::Display for Counts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} error{}, {} warning{}",
+            self.errors,
+            plural(self.errors),
+            self.warnings,
+            plural(self.warnings)
+        )
+    }
+}
+
+/// Render `diagnostic` as a single line like `12:5 error[E0308]: message`
+fn short(config: &Diagnostics, color: bool, diagnostic: &Diagnostic) -> String {
+    let level = match diagnostic.level {
+        Level::Error => paint(color, "1;31", "error"),
+        Level::Warning => paint(color, "1;33", "warning"),
+        level => level.as_str().to_string(),
+    };
+    let code = diagnostic
+        .code
+        .as_ref()
+        .map(|c| format!("[{}]", c.code))
+        .unwrap_or_default();
+    let location = match diagnostic.primary_span() {
+        Some(span) => {
+            let text = format!("{}:{}", span.line_start, span.column_start);
+            format!("{} ", link(config, &span.path, Some(span), &text))
+        }
+        None => String::new(),
+    };
+    format!("{location}{level}{code}: {}", diagnostic.message)
+}
+
+/// Turn the `--> file:line:col` line of the compiler's rendering into a
+/// hyperlink
+fn link_rendered(
+    config: &Diagnostics,
+    diagnostic: &Diagnostic,
+    rendered: &str,
+) -> String {
+    let (Some(_), Some(span)) = (&config.hyperlink_format, diagnostic.primary_span())
+    else {
+        return rendered.trim_end().to_string();
+    };
+    let location = format!(
+        "{}:{}:{}",
+        span.file_name.display(),
+        span.line_start,
+        span.column_start
+    );
+    rendered.trim_end().replacen(
+        &location,
+        &link(config, &span.path, Some(span), &location),
+        1,
+    )
+}
+
+/// Wrap `text` in a terminal hyperlink to the absolute `path` (at `span`), if
+/// configured
+fn link(config: &Diagnostics, path: &Path, span: Option<&Span>, text: &str) -> String {
+    let Some(format) = &config.hyperlink_format else {
+        return text.to_string();
+    };
+    let template = match format.as_str() {
+        "file" => "file://{path}",
+        "vscode" => "vscode://file{path}:{line}:{column}",
+        template => template,
+    };
+    let url = template
+        .replace("{path}", &path.display().to_string())
+        .replace("{line}", &span.map_or(1, |s| s.line_start).to_string())
+        .replace("{column}", &span.map_or(1, |s| s.column_start).to_string());
+    format!("\x1b]8;;{url}\x1b\\{text}\x1b]8;;\x1b\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let summaries = [
+            Diagnostic::new("error", "aborting due to 2 previous errors", None),
+            Diagnostic::new("warning", "1 warning emitted", None),
+            Diagnostic::new(
+                "failure-note",
+                "For more information about this error, try `rustc --explain E0308`.",
+                None,
+            ),
+        ];
+        for summary in &summaries {
+            assert!(summary.is_summary(), "{:?}", summary.message);
+        }
+        assert_eq!(summaries[2].level, Level::FailureNote);
+
+        // Internal compiler errors are failures like any other error
+        let ice =
+            Diagnostic::new("error: internal compiler error", "unexpected panic", None);
+        assert!(!ice.is_summary());
+        assert_eq!(ice.level, Level::Error);
+        assert_eq!(Counts::of([&ice]).errors, 1);
+        let rendered = render(std::slice::from_ref(&ice), &Diagnostics::default());
+        assert!(rendered.contains("unexpected panic"));
+        assert!(!Diagnostic::new("other", "something new", None).is_summary());
+
+        assert!(
+            !Diagnostic::new("error", "mismatched types", Some("src/main.rs"))
+                .is_summary()
+        );
+        assert!(!Diagnostic::new("error", "linking with `cc` failed", None).is_summary());
+    }
+
+    #[test]
+    fn summaries_are_left_out() {
+        let diagnostics = [
+            Diagnostic::new("error", "mismatched types", Some("src/main.rs")),
+            Diagnostic::new("warning", "unused variable: `x`", Some("src/main.rs")),
+            Diagnostic::new("error", "aborting due to 1 previous error", None),
+            Diagnostic::new(
+                "failure-note",
+                "Some errors have detailed explanations",
+                None,
+            ),
+        ];
+        let counts = Counts::of(&diagnostics);
+        assert_eq!((counts.errors, counts.warnings), (1, 1));
+
+        let config = Diagnostics {
+            style: Style::Short,
+            ..Diagnostics::default()
+        };
+        let rendered = render(&diagnostics, &config);
+        assert!(rendered.contains("mismatched types"));
+        assert!(!rendered.contains("(no location)"));
+        assert!(!rendered.contains("aborting"));
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, LineWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+/// How lifecycle events are written
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Format {
+    /// One JSON object per line
+    Json,
+}
+
+struct Sink {
+    out:    Mutex<Box<dyn Write + Send>>,
+    stdout: bool,
+}
+
+/// Something that happened to watchf, a task or a service
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Lifecycle {
+    ChangesDetected {
+        paths: Vec<PathBuf>,
+    },
+    TaskStarted {
+        task:    String,
+        command: String,
+        pid:     u32,
+    },
+    TaskFinished {
+        task:        String,
+        ok:          bool,
+        duration_ms: u64,
+        errors:      usize,
+        warnings:    usize,
+    },
+    TaskCancelled {
+        task: String,
+    },
+    ProcessSpawned {
+        service: String,
+        pid:     u32,
+    },
+    ServiceReady {
+        service:  String,
+        after_ms: u64,
+    },
+    ProcessExited {
+        service: String,
+        pid:     u32,
+        /// Like "exited with code 1"
+        status:  String,
+        code:    Option<i32>,
+        signal:  Option<i32>,
+        /// Whether watchf stopped it, as opposed to it exiting on its own
+        stopped: bool,
+    },
+    ShuttingDown,
+}
+
+/// Write every event from now on to `file`, or to stdout without one. The
+/// output of the processes then goes to stderr only.
+pub fn init(_format: Format, file: Option<&Path>) -> io::Result<()> {
+    let out: Box<dyn Write + Send> = match file {
+        Some(path) => Box::new(LineWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    let _ = SINK.set(Sink {
+        out:    Mutex::new(out),
+        stdout: file.is_none(),
+    });
+    Ok(())
+}
+
+/// Whether events are written to stdout, which nothing else may write to then
+pub fn on_stdout() -> bool {
+    SINK.get().is_some_and(|sink| sink.stdout)
+}
+
+/// Write `event` with the time it happened at, if events are enabled
+pub fn emit(event: Lifecycle) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let Ok(serde_json::Value::Object(mut object)) = serde_json::to_value(&event) else {
+        return;
+    };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    object.insert("time_ms".to_string(), (time.as_millis() as u64).into());
+    let mut line = serde_json::Value::Object(object).to_string();
+    line.push('\n');
+
+    let mut out = sink.out.lock().unwrap();
+    let _ = out.write_all(line.as_bytes());
+    let _ = out.flush();
+}
@@ -0,0 +1,222 @@
+use crate::output::log;
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Ignore files that are honored in and above the watched directories. Within
+/// one directory, `.ignore` takes precedence over `.gitignore`.
+const IGNORE_FILES: [&str; 2] = [".ignore", ".gitignore"];
+
+/// Decides which paths under the watched directories should never trigger a
+/// rebuild
+pub struct PathFilter {
+    /// The patterns from the config, which take precedence over ignore files
+    patterns:      Gitignore,
+    /// Matchers for ignore files, ordered from the most to the least specific.
+    /// The first one with an opinion on a path wins.
+    matchers:      Vec<Gitignore>,
+    /// Ignore everything inside of `.git` directories
+    skip_git_dirs: bool,
+}
+
+impl PathFilter {
+    /// Build a filter from the configured `patterns` (gitignore syntax,
+    /// relative to the current directory) and, if `ignore_files` is set, the
+    /// ignore files found in and above each of the `roots`.
+    pub fn new(
+        patterns: &[String],
+        roots: &[PathBuf],
+        ignore_files: bool,
+    ) -> eyre::Result<Self> {
+        let mut builder = GitignoreBuilder::new(std::env::current_dir()?);
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+
+        let mut filter = Self {
+            patterns:      builder.build()?,
+            matchers:      Vec::new(),
+            skip_git_dirs: ignore_files,
+        };
+        if !ignore_files {
+            return Ok(filter);
+        }
+
+        let mut loaded = HashSet::new();
+        for root in roots {
+            let root = std::path::absolute(root)?;
+            // Only look upwards until the root of the repository
+            for dir in root.ancestors().skip(1) {
+                filter.load_ignore_files(dir, &mut loaded);
+                if dir.join(".git").exists() {
+                    break;
+                }
+            }
+            filter.walk(&root, &mut loaded);
+        }
+        // The sort is stable, so the order within a directory is kept
+        filter
+            .matchers
+            .sort_by_key(|m| Reverse(m.path().components().count()));
+        Ok(filter)
+    }
+
+    /// Whether `path` is excluded by any pattern or ignore file
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Ok(path) = std::path::absolute(path) else {
+            return false;
+        };
+        if self.skip_git_dirs && path.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+
+        for matcher in std::iter::once(&self.patterns).chain(&self.matchers) {
+            if !path.starts_with(matcher.path()) {
+                continue;
+            }
+            match matcher.matched_path_or_any_parents(&path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// Load the ignore files of `dir` and all of its subdirectories that
+    /// aren't ignored themselves
+    fn walk(&mut self, dir: &Path, loaded: &mut HashSet<PathBuf>) {
+        self.load_ignore_files(dir, loaded);
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|t| t.is_dir())
+                && !self.is_ignored(&path, true)
+            {
+                self.walk(&path, loaded);
+            }
+        }
+    }
+
+    /// Add the ignore files directly inside of `dir` in front of the existing
+    /// matchers, unless they were loaded before
+    fn load_ignore_files(&mut self, dir: &Path, loaded: &mut HashSet<PathBuf>) {
+        if !loaded.insert(dir.to_path_buf()) {
+            return;
+        }
+        for name in IGNORE_FILES.iter().rev() {
+            let file = dir.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            let (matcher, err) = Gitignore::new(&file);
+            if let Some(e) = err {
+                log!("Failed to parse {}: {e}", file.display());
+            }
+            self.matchers.insert(0, matcher);
+        }
+    }
+}
+
+/// A set of gitignore-style globs, relative to the current directory
+pub struct Globs(Gitignore);
+
+impl Globs {
+    pub fn new(patterns: &[String]) -> eyre::Result<Self> {
+        let mut builder = GitignoreBuilder::new(std::env::current_dir()?);
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(Self(builder.build()?))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether `path` or any of its parent directories matches
+    pub fn matches(&self, path: &Path) -> bool {
+        let Ok(path) = std::path::absolute(path) else {
+            return false;
+        };
+        path.starts_with(self.0.path())
+            && self
+                .0
+                .matched_path_or_any_parents(&path, path.is_dir())
+                .is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `files`, given as paths relative to `dir` and their contents
+    fn write(dir: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let base = crate::scratch_dir("filter");
+        let repo = base.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        write(
+            &base,
+            &[
+                // Above the root of the repository
+                (".gitignore", "*.md\n"),
+                ("repo/.gitignore", "*.tmp\nbuild/\n"),
+                ("repo/src/.gitignore", "!notes.tmp\ngenerated.rs\n"),
+                ("repo/src/.ignore", "!generated.rs\n"),
+            ],
+        );
+        let patterns = ["*.log", "!important.tmp"].map(String::from);
+        let src = repo.join("src");
+        let filter =
+            PathFilter::new(&patterns, std::slice::from_ref(&src), true).unwrap();
+        let ignored = |path: &str| filter.is_ignored(&src.join(path), false);
+
+        assert!(!ignored("main.rs"));
+        // Ignore files in parent directories apply
+        assert!(ignored("a.tmp"));
+        // More specific ignore files take precedence
+        assert!(!ignored("notes.tmp"));
+        // `.ignore` takes precedence over `.gitignore`
+        assert!(!ignored("generated.rs"));
+        // The configured patterns take precedence over ignore files
+        assert!(ignored("debug.log"));
+        assert!(!ignored("important.tmp"));
+        // Nothing above the repository is loaded
+        assert!(!ignored("README.md"));
+        assert!(filter.is_ignored(&src.join("build"), true));
+        assert!(ignored("build/out.rs"));
+        assert!(filter.is_ignored(&repo.join(".git/HEAD"), false));
+    }
+
+    #[test]
+    fn without_ignore_files() {
+        let base = crate::scratch_dir("filter-patterns");
+        write(&base, &[(".gitignore", "*.tmp\n")]);
+        let patterns = ["*.log".to_string()];
+        let filter =
+            PathFilter::new(&patterns, std::slice::from_ref(&base), false).unwrap();
+
+        assert!(filter.is_ignored(&base.join("debug.log"), false));
+        assert!(!filter.is_ignored(&base.join("a.tmp"), false));
+        assert!(!filter.is_ignored(&base.join(".git/HEAD"), false));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    Message,
+    output::{self, log},
+    process::Exited,
+    tui::Pane,
+};
+use serde::Deserialize;
+use std::{
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc::Sender,
+    thread,
+};
+
+/// Commands run around the tasks and services. They get the context through
+/// `WATCHF_*` environment variables, and those run before a step can prevent it
+/// by failing.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Hooks {
+    /// Runs before the command of a task. Gets `WATCHF_TASK` and
+    /// `WATCHF_CHANGED_PATHS`, and can skip the task.
+    pub pre_build:  Option<Vec<String>>,
+    /// Runs after a task succeeded. Gets `WATCHF_TASK`, `WATCHF_DURATION_MS`
+    /// and `WATCHF_ARTIFACTS`, and can fail the task so nothing depending on
+    /// it runs.
+    pub post_build: Option<Vec<String>>,
+    /// Runs after a task failed. Gets `WATCHF_TASK`, `WATCHF_DURATION_MS` and
+    /// `WATCHF_EXIT_STATUS`.
+    pub on_failure: Option<Vec<String>>,
+    /// Runs before a service is spawned. Gets `WATCHF_SERVICE` and `WATCHF_BIN`
+    /// if it runs a build artifact, and can keep it from starting.
+    pub pre_run:    Option<Vec<String>>,
+    /// Runs after a service exited. Gets `WATCHF_SERVICE`, `WATCHF_PID` and
+    /// `WATCHF_EXIT_STATUS`, and can keep it from being restarted.
+    pub post_exit:  Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PreBuild,
+    PostBuild,
+    OnFailure,
+    PreRun,
+    PostExit,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PreBuild => "pre-build",
+            Self::PostBuild => "post-build",
+            Self::OnFailure => "on-failure",
+            Self::PreRun => "pre-run",
+            Self::PostExit => "post-exit",
+        }
+    }
+}
+
+impl Hooks {
+    fn command(&self, hook: Hook) -> Option<&[String]> {
+        let cmd = match hook {
+            Hook::PreBuild => &self.pre_build,
+            Hook::PostBuild => &self.post_build,
+            Hook::OnFailure => &self.on_failure,
+            Hook::PreRun => &self.pre_run,
+            Hook::PostExit => &self.post_exit,
+        };
+        cmd.as_deref().filter(|cmd| !cmd.is_empty())
+    }
+
+    /// Run `hook` with the variables in `env` and wait for it. Returns whether
+    /// the next step may go ahead, which it always may without the hook.
+    pub fn run(&self, hook: Hook, env: &[(&str, String)]) -> bool {
+        let Some(cmd) = self.command(hook) else {
+            return true;
+        };
+        let mut command = Command::new(&cmd[0]);
+        command
+            .args(&cmd[1..])
+            .env("WATCHF_HOOK", hook.name())
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                log!("Failed to run the {} hook: {e}", hook.name());
+                return false;
+            }
+        };
+        let forwarding = output::forward(&mut child, hook.name(), Pane::Tasks, None);
+        let status = child.wait();
+        // Show everything the hook printed before what became of it
+        for thread in forwarding {
+            let _ = thread.join();
+        }
+        match status {
+            Ok(status) if status.success() => true,
+            Ok(status) => {
+                log!("The {} hook {}", hook.name(), Exited(status));
+                false
+            }
+            Err(e) => {
+                log!("Failed to wait for the {} hook: {e}", hook.name());
+                false
+            }
+        }
+    }
+
+    /// Run `hook` like [`Hooks::run`] on a thread of its own. Whether the next
+    /// step may go ahead is sent to `tx` as a [`Message::Hook`] with the given
+    /// `id`.
+    pub fn spawn(
+        &self,
+        hook: Hook,
+        env: Vec<(&'static str, String)>,
+        id: u64,
+        tx: Sender<Message>,
+    ) {
+        let hooks = self.clone();
+        thread::spawn(move || {
+            let ok = hooks.run(hook, &env);
+            // The main loop only goes away when watchf exits
+            let _ = tx.send(Message::Hook { id, ok });
+        });
+    }
+}
+
+/// `paths` as the value of an environment variable, one per line
+pub fn paths<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> String {
+    paths
+        .into_iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,104 @@
+use crate::Message;
+use std::{
+    io::{self, IsTerminal, Read},
+    mem::MaybeUninit,
+    sync::mpsc::Sender,
+    thread,
+};
+
+/// The keys and what they do, shown when watchf starts
+pub const HELP: &str =
+    "Keys: r rebuild, R restart, c clear, p pause/resume, k kill, q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    /// Run every task again, as if everything changed
+    Rebuild,
+    /// Restart the services without running any tasks
+    Restart,
+    /// Clear the screen
+    Clear,
+    /// Stop or resume reacting to changes
+    Pause,
+    /// Stop the services until they're restarted
+    Kill,
+    /// Shut down
+    Quit,
+}
+
+impl Key {
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            'r' => Self::Rebuild,
+            'R' => Self::Restart,
+            'c' => Self::Clear,
+            'p' => Self::Pause,
+            'k' => Self::Kill,
+            'q' => Self::Quit,
+            _ => return None,
+        })
+    }
+}
+
+/// Keeps the terminal reading single key presses without echoing them, until
+/// it's dropped
+pub struct Keys {
+    original: libc::termios,
+}
+
+impl Keys {
+    /// Send the keys pressed to `tx` as [`Message::Key`], if stdin is a
+    /// terminal watchf runs in the foreground of
+    pub fn spawn(tx: Sender<Message>) -> io::Result<Option<Self>> {
+        // A background job would be stopped by SIGTTOU when changing the
+        // terminal
+        // SAFETY: Both only query the process groups
+        let foreground =
+            unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() };
+        if !io::stdin().is_terminal() || !foreground {
+            return Ok(None);
+        }
+
+        let mut original = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `tcgetattr` initializes `original` when it succeeds
+        let original = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            original.assume_init()
+        };
+        // Ctrl-C keeps working as usual, since `ISIG` stays set
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        set_attributes(&raw)?;
+
+        thread::spawn(move || {
+            let mut stdin = io::stdin().lock();
+            let mut byte = [0];
+            while let Ok(1) = stdin.read(&mut byte) {
+                if let Some(key) = Key::from_char(byte[0] as char)
+                    && tx.send(Message::Key(key)).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Ok(Some(Self { original }))
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        let _ = set_attributes(&self.original);
+    }
+}
+
+fn set_attributes(termios: &libc::termios) -> io::Result<()> {
+    // SAFETY: `termios` is a valid `termios` struct
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
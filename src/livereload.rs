@@ -0,0 +1,126 @@
+use crate::output::log;
+use eyre::WrapErr;
+use serde::Deserialize;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The script that reloads the page it's included in, served at
+/// `/livereload.js`. `ADDRESS` is replaced with the address of the server.
+const SCRIPT: &str = r#"(() => {
+  const events = new EventSource("http://ADDRESS/events");
+  events.addEventListener("reload", () => location.reload());
+})();
+"#;
+
+/// Settings for the server that tells browsers to reload the page
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct LiveReload {
+    /// The address the server listens on
+    pub address: String,
+}
+
+impl Default for LiveReload {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:35729".to_string(),
+        }
+    }
+}
+
+/// The browsers listening for reloads through server-sent events
+pub struct Browsers {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Browsers {
+    /// Serve the event stream at `/events` and the script that reloads a page
+    /// with it at `/livereload.js`
+    pub fn spawn(config: &LiveReload) -> eyre::Result<Self> {
+        let listener = TcpListener::bind(&config.address)
+            .wrap_err_with(|| format!("Failed to listen on {}", config.address))?;
+        log!(
+            "Live reload: add <script src=\"http://{}/livereload.js\"></script> to \
+             the page",
+            config.address
+        );
+
+        let clients = Arc::<Mutex<Vec<TcpStream>>>::default();
+        let script = SCRIPT.replace("ADDRESS", &config.address);
+        thread::spawn({
+            let clients = clients.clone();
+            move || {
+                for stream in listener.incoming().map_while(Result::ok) {
+                    let clients = clients.clone();
+                    let script = script.clone();
+                    thread::spawn(move || serve(stream, &script, &clients));
+                }
+            }
+        });
+        Ok(Self { clients })
+    }
+
+    /// Tell every browser to reload the page, forgetting those that went away
+    pub fn reload(&self) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|c| c.write_all(b"event: reload\ndata: \n\n").is_ok());
+        if !clients.is_empty() {
+            log!("Reloading {} browser tab(s)", clients.len());
+        }
+    }
+}
+
+/// Answer a single request, keeping the connection around if it's for the
+/// event stream
+fn serve(mut stream: TcpStream, script: &str, clients: &Mutex<Vec<TcpStream>>) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut lines = BufReader::new(reader).lines().map_while(Result::ok);
+    // Like `GET /events HTTP/1.1`
+    let Some(request) = lines.next() else {
+        return;
+    };
+    // The headers don't matter, but they have to be read
+    lines.take_while(|l| !l.is_empty()).for_each(drop);
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+    let cors = "Access-Control-Allow-Origin: *\r\n";
+    let result = match path {
+        "/events" => stream
+            .write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\n{cors}Content-Type: text/event-stream\r\n\
+                     Cache-Control: no-cache\r\n\r\n: connected\n\n"
+                )
+                .as_bytes(),
+            )
+            .map(|()| {
+                let _ = stream.set_read_timeout(None);
+                clients.lock().unwrap().push(stream);
+            }),
+        "/livereload.js" => stream.write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\n{cors}Content-Type: text/javascript\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{script}",
+                script.len()
+            )
+            .as_bytes(),
+        ),
+        _ => stream.write_all(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ),
+    };
+    if let Err(e) = result {
+        log!("Live reload: failed to answer {request:?}: {e}");
+    }
+}
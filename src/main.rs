@@ -1,17 +1,55 @@
 #![feature(exit_status_error)]
 
+mod build;
+mod control;
+mod debounce;
+mod diagnostics;
+mod events;
+mod filter;
+mod hooks;
+mod keys;
+mod livereload;
+mod output;
+mod process;
+mod proxy;
+mod quickfix;
+mod ready;
+mod rollback;
+mod run;
+mod sockets;
+mod tasks;
+mod testing;
+mod triggers;
+mod tui;
+
+use build::{Build, BuildPolicy, Check, Outcome};
 use clap::Parser;
-use eyre::Context;
+use control::{Request, Response, ServiceStatus, Status, TaskStatus};
+use debounce::{ChangeSet, Debounce};
+use diagnostics::{Counts, Diagnostic, Diagnostics};
+use events::Lifecycle;
+use filter::PathFilter;
+use hooks::{Hook, Hooks};
+use keys::{Key, Keys};
+use livereload::{Browsers, LiveReload};
 use notify::{EventKind, RecursiveMode, Watcher};
+use output::{Output, log};
+use process::Signal;
+use proxy::{Gate, Proxy};
+use run::{Artifact, Restart, Runner, Service};
 use serde::Deserialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
-    process::{Child, Command, Stdio},
-    sync::mpsc,
-    time::SystemTime,
+    process::Command,
+    sync::mpsc::{self, RecvError, RecvTimeoutError, Sender},
+    time::{Duration, Instant, SystemTime},
 };
+use tasks::{Cycle, Graph, Kind, Task};
+use testing::Test;
+use triggers::{Action, Route, Trigger, Triggers};
+use tui::{Event, Tui};
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
@@ -19,6 +57,15 @@ struct Args {
     /// Name of the person to greet
     #[arg(short, long, default_value = "watchf.toml")]
     config_path: PathBuf,
+    /// Show a dashboard instead of printing lines, if stdout is a terminal
+    #[arg(long)]
+    tui:         bool,
+    /// Report what happens as machine-readable events on stdout
+    #[arg(long, value_name = "FORMAT")]
+    events:      Option<events::Format>,
+    /// Write the events to this file instead of stdout
+    #[arg(long, value_name = "PATH", requires = "events")]
+    events_file: Option<PathBuf>,
     #[command(subcommand)]
     command:     Subcommand,
 }
@@ -27,20 +74,136 @@ struct Args {
 enum Subcommand {
     /// Build using the configured build command
     Build,
-    /// Run using the configured run command
-    Run,
+    /// Run the run command along with every configured service
+    Run {
+        /// Run this task and its dependencies instead
+        task: Option<String>,
+    },
+    /// Run the tests using the configured test runner
+    Test,
+    /// Show the outcome of the latest run of each task of a running watchf
+    Status,
+    /// Make a running watchf run tasks and everything depending on them
+    Trigger {
+        /// The tasks to run, everything if none are given
+        tasks: Vec<String>,
+    },
+    /// Make a running watchf restart services
+    Restart {
+        /// The services to restart, all of them if none are given
+        services: Vec<String>,
+    },
+    /// Make a running watchf stop reacting to changes
+    Pause,
+    /// Make a paused watchf react to changes again
+    Resume,
+    /// Show the output of a running watchf
+    Logs,
+}
+
+impl Subcommand {
+    /// The request to send to a running watchf, if this is one of the client
+    /// commands
+    fn request(&self) -> Option<Request> {
+        match self {
+            Self::Build | Self::Run { .. } | Self::Test => None,
+            Self::Status => Some(Request::Status),
+            Self::Trigger { tasks } => Some(Request::Trigger {
+                tasks: tasks.clone(),
+            }),
+            Self::Restart { services } => Some(Request::Restart {
+                services: services.clone(),
+            }),
+            Self::Pause => Some(Request::Pause),
+            Self::Resume => Some(Request::Resume),
+            Self::Logs => Some(Request::Logs),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 struct Config {
     /// The build command to use
+    #[serde(default)]
     build_cmd: Vec<String>,
     /// The run command to use
+    #[serde(default)]
     run_cmd:   Vec<String>,
+    /// A quick check that has to pass before the build command runs
+    check:     Option<Check>,
+    /// Run the binary from the build artifacts instead of the run command
+    artifact:  Option<Artifact>,
+
+    /// The signal the run command is asked to exit with before restarting it
+    #[serde(default)]
+    stop_signal:     Signal,
+    /// Milliseconds to wait for the run command to exit before killing it
+    #[serde(default = "default_stop_timeout")]
+    stop_timeout_ms: u64,
+    /// What happens when the run command exits on its own
+    #[serde(default)]
+    restart:         Restart,
+    /// Addresses the run command gets listening sockets for, see
+    /// [`Service::sockets`]
+    #[serde(default)]
+    sockets:         Vec<String>,
 
     /// Files/directories to watch
-    watch: Vec<PathBuf>,
+    watch:          Vec<PathBuf>,
+    /// Gitignore-style patterns of paths that never trigger a rebuild
+    #[serde(default)]
+    ignore:         Vec<String>,
+    /// Honor `.gitignore` and `.ignore` files in and above the watched
+    /// directories
+    #[serde(default = "default_true")]
+    gitignore:      bool,
+    /// How filesystem events are batched into a single rebuild
+    #[serde(default)]
+    debounce:       Debounce,
+    /// What to do with a running build when new changes arrive
+    #[serde(default)]
+    build_policy:   BuildPolicy,
+    /// How compiler diagnostics are shown
+    #[serde(default)]
+    diagnostics:    Diagnostics,
+    /// How the `test` subcommand runs the tests
+    #[serde(default)]
+    test:           Test,
+    /// Named tasks, in addition to the ones defined by the shorthands above
+    #[serde(default)]
+    tasks:          BTreeMap<String, Task>,
+    /// Rules for what changes to specific paths do, checked in order
+    #[serde(default)]
+    triggers:       Vec<Trigger>,
+    /// Long-running programs supervised together, in addition to the run
+    /// command
+    #[serde(default)]
+    services:       BTreeMap<String, Service>,
+    /// A Procfile to read more services from
+    procfile:       Option<PathBuf>,
+    /// How the output of watchf and its processes is shown
+    #[serde(default)]
+    output:         Output,
+    /// Serve an event stream that tells browsers to reload once the services
+    /// restarted
+    live_reload:    Option<LiveReload>,
+    /// A reverse proxy in front of a service
+    proxy:          Option<Proxy>,
+    /// Commands run around the tasks and services
+    #[serde(default)]
+    hooks:          Hooks,
+    /// The Unix socket the client commands talk to watchf through
+    #[serde(default = "control::default_socket")]
+    control_socket: PathBuf,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_stop_timeout() -> u64 {
+    5000
 }
 
 impl Config {
@@ -48,149 +211,1130 @@ impl Config {
         let contents = std::fs::read_to_string(from)?;
         Ok(toml::de::from_str(&contents)?)
     }
+
+    /// The configured services, including those from the Procfile and `run` if
+    /// the run command is configured
+    fn services(&self) -> eyre::Result<BTreeMap<String, Service>> {
+        let mut services = match &self.procfile {
+            Some(path) => run::procfile(path)?,
+            None => BTreeMap::new(),
+        };
+        services.extend(self.services.clone());
+
+        if !self.run_cmd.is_empty() || self.artifact.is_some() {
+            let build = !self.build_cmd.is_empty() || self.tasks.contains_key("build");
+            services
+                .entry("run".to_string())
+                .or_insert_with(|| Service {
+                    cmd: self.run_cmd.clone(),
+                    artifact: self.artifact.clone(),
+                    restart: self.restart.clone(),
+                    sockets: self.sockets.clone(),
+                    depends_on: build.then(|| "build".to_string()).into_iter().collect(),
+                    ..Service::default()
+                });
+        }
+        for (name, service) in &services {
+            eyre::ensure!(
+                !service.cmd.is_empty() || service.artifact.is_some(),
+                "Service {name:?} has neither `cmd` nor `artifact`"
+            );
+        }
+        Ok(services)
+    }
+
+    /// The configured tasks, including `check`, `build` and `test` if they're
+    /// configured through the shorthands and not defined as tasks, and one for
+    /// each of `services`
+    fn tasks(
+        &self,
+        services: &BTreeMap<String, Service>,
+    ) -> eyre::Result<BTreeMap<String, Task>> {
+        let mut tasks = self.tasks.clone();
+        let check = self.check.as_ref().map(|check| Task {
+            cmd: check.cmd.clone(),
+            kind: Kind::Cargo,
+            deny_warnings: check.deny_warnings,
+            ..Task::default()
+        });
+        let after_check = || match check {
+            Some(_) => vec!["check".to_string()],
+            None => Vec::new(),
+        };
+
+        if !self.build_cmd.is_empty() {
+            tasks.entry("build".to_string()).or_insert_with(|| Task {
+                cmd: self.build_cmd.clone(),
+                kind: Kind::Cargo,
+                depends_on: after_check(),
+                ..Task::default()
+            });
+        }
+        // A service may be called `test` as well
+        if !services.contains_key("test") {
+            tasks.entry("test".to_string()).or_insert_with(|| Task {
+                kind: Kind::Test,
+                depends_on: after_check(),
+                ..Task::default()
+            });
+        }
+        if let Some(check) = check {
+            tasks.entry("check".to_string()).or_insert(check);
+        }
+        for (name, service) in services {
+            eyre::ensure!(
+                !tasks.contains_key(name),
+                "{name:?} is configured as both a task and a service"
+            );
+            let task = Task {
+                kind: Kind::Service,
+                cwd: service.cwd.clone(),
+                env: service.env.clone(),
+                depends_on: service.depends_on.clone(),
+                inputs: service.inputs.clone(),
+                ..Task::default()
+            };
+            tasks.insert(name.clone(), task);
+        }
+        Ok(tasks)
+    }
+}
+
+/// Everything the main loop reacts to
+enum Message {
+    /// A filesystem event from the watcher
+    Watch(notify::Result<notify::Event>),
+    /// The command of a task finished, was cancelled or failed
+    Built { id: u64, outcome: Outcome },
+    /// A hook run for the task with the given id finished, allowing the task
+    /// to go ahead or not
+    Hook { id: u64, ok: bool },
+    /// A service was spawned with the given pid, or failed to start
+    Spawned {
+        service: String,
+        result:  Result<u32, String>,
+    },
+    /// A service stopped or exited on its own, and what became of it
+    Exited {
+        service: String,
+        status:  ServiceStatus,
+    },
+    /// A service became ready after starting, or failed to
+    Ready {
+        service: String,
+        result:  Result<Duration, String>,
+    },
+    /// A client sent a request through the control socket
+    Control {
+        request: Request,
+        reply:   Sender<Response>,
+    },
+    /// A key was pressed
+    Key(Key),
+    /// watchf was interrupted or asked to terminate
+    Shutdown,
+}
+
+/// A task waiting for a hook to finish
+enum Pending {
+    /// Start `cmd` once the pre-build hook allows it
+    Build { task: String, cmd: Command },
+    /// Record the outcome of the command once the post-build or on-failure
+    /// hook finished. `ok` is whether the command succeeded.
+    Finish {
+        task:    String,
+        outcome: Outcome,
+        ok:      bool,
+    },
 }
 
 fn main() -> eyre::Result<()> {
     let args = Args::parse();
     let config = Config::load(&args.config_path)?;
+    if let Some(request) = args.command.request() {
+        return control::request(&config.control_socket, &request);
+    }
+    output::init(config.output.clone());
+    if let Some(format) = args.events {
+        eyre::ensure!(
+            !args.tui || args.events_file.is_some(),
+            "The dashboard and the events can't both use stdout, pass --events-file"
+        );
+        events::init(format, args.events_file.as_deref())?;
+    }
+    let filter = PathFilter::new(&config.ignore, &config.watch, config.gitignore)?;
+    let services = config.services()?;
+    let graph = Graph::new(config.tasks(&services)?)?;
+    let triggers = Triggers::new(&config.triggers, &graph)?;
+    let entry = entry(&args.command, &services, &graph)?;
+    let scope = scope(&args.command, &entry, &graph, &triggers);
+
     let mut bins = HashMap::new();
-    let mut rebuild = true;
+    // The executables of the latest successful run of each cargo task
+    let mut artifacts = BTreeMap::<String, Vec<PathBuf>>::new();
     let mut last_rebuild = SystemTime::now();
+    let mut changes = ChangeSet::default();
+    // The paths that changed since the last cycle started, and before it
+    let mut changed = BTreeSet::new();
+    let mut cycle_changed = String::new();
+    // The first cycle runs every task
+    let mut queued = scope.clone();
+    let mut cycle: Option<Cycle> = None;
+    // The diagnostics of the latest run of each cargo task, written to the
+    // error files once per cycle
+    let mut errors = BTreeMap::<String, Vec<Diagnostic>>::new();
+    // Services that are signalled instead of restarted in the next cycle, and
+    // the triggered tasks services wait for
+    let mut reload = BTreeMap::new();
+    let mut after = BTreeMap::<String, BTreeSet<String>>::new();
+    // Services running a build artifact that are only restarted in the next
+    // cycle if it was rebuilt
+    let mut if_rebuilt = BTreeSet::new();
+    let mut running = HashMap::<u64, Build>::new();
+    // Tasks waiting for their hooks, by the id their command runs with
+    let mut waiting = HashMap::<u64, Pending>::new();
+    let mut succeeded = HashSet::new();
+    let mut build_id = 0;
+    let mut tests = testing::Session::default();
+    let mut paused = false;
+    let mut rebuild = false;
+    // The latest run of each task, for the `status` command
+    let mut task_status = BTreeMap::new();
+    // Browsers are reloaded once the restarted services are up, or ready if
+    // they have readiness checks
+    let mut refresh = false;
+    let mut awaiting = BTreeSet::new();
 
-    let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
-    let (run_tx, run_rx) = mpsc::channel::<()>();
+    let (tx, rx) = mpsc::channel::<Message>();
 
-    let mut watcher = notify::recommended_watcher(tx)?;
+    ctrlc::set_handler({
+        let tx = tx.clone();
+        move || {
+            let _ = tx.send(Message::Shutdown);
+        }
+    })?;
+
+    let mut watcher = notify::recommended_watcher({
+        let tx = tx.clone();
+        move |res: notify::Result<notify::Event>| {
+            let _ = tx.send(Message::Watch(res));
+        }
+    })?;
 
     for f in config.watch.iter().map(PathBuf::as_path) {
         watcher.watch(f, RecursiveMode::Recursive)?;
     }
 
-    // Use a condvar instead?
-    if args.command == Subcommand::Run {
-        std::thread::spawn({
-            let run_cmd = config.run_cmd.clone();
-            move || {
-                let mut prog: Option<Child> = None;
-
-                for _ in run_rx {
-                    if let Some(mut p) = prog {
-                        eprintln!("Killing child with pid {}...", p.id());
-                        p.kill()
-                            .context(format!("Failed to kill child with pid {}", p.id()))
-                            .unwrap();
-                        p.wait().unwrap();
-                    }
-
-                    eprintln!("Running run command: {:?}", run_cmd);
-                    prog = Some(
-                        Command::new(&run_cmd[0])
-                            .args(&run_cmd[1..])
-                            .spawn()
-                            .unwrap(),
-                    );
-                }
-            }
-        });
+    // Both restore the terminal when dropped
+    let mut tui = if args.tui {
+        Tui::spawn(tx.clone())?
+    } else {
+        None
+    };
+    // Another session in the same project may be listening already
+    let serving = match control::serve(&config.control_socket, tx.clone()) {
+        Ok(()) => true,
+        Err(e) => {
+            log!("Client commands won't reach this session: {e}");
+            false
+        }
+    };
+
+    let keys = match tui {
+        Some(_) => None,
+        None => Keys::spawn(tx.clone())?,
+    };
+    if keys.is_some() {
+        log!("{}", keys::HELP);
     }
 
+    let mut runners = services
+        .iter()
+        .filter(|(name, _)| scope.contains(*name))
+        .map(|(name, service)| {
+            let runner = Runner::spawn(name, service, &config, tx.clone())?;
+            Ok((name.clone(), runner))
+        })
+        .collect::<eyre::Result<BTreeMap<_, _>>>()?;
+    let running_services = runners.keys().cloned().collect::<Vec<_>>();
+    // What each service is up to, for the `status` command
+    let mut service_status = running_services
+        .iter()
+        .map(|name| (name.clone(), ServiceStatus::Stopped))
+        .collect::<BTreeMap<_, _>>();
+    let browsers = config
+        .live_reload
+        .as_ref()
+        .map(Browsers::spawn)
+        .transpose()?;
+    let gate = match &config.proxy {
+        Some(proxy) => {
+            eyre::ensure!(
+                runners.contains_key(&proxy.service),
+                "The proxy is in front of {:?}, which isn't a running service",
+                proxy.service
+            );
+            Some(Gate::spawn(proxy)?)
+        }
+        None => None,
+    };
+
     loop {
-        if rebuild {
-            rebuild = false;
-            last_rebuild = SystemTime::now();
-            match build(&config) {
-                Err(e) => {
-                    eprintln!("Build failed: {e}");
+        // Start every task that's ready, and the queued ones once the current
+        // cycle is finished. Services are done as soon as they're restarted.
+        loop {
+            if cycle.as_ref().is_none_or(Cycle::is_finished) {
+                if cycle.take().is_some()
+                    && let Err(e) =
+                        quickfix::write(errors.values().flatten(), &config.diagnostics)
+                {
+                    log!("Failed to write error file: {e}");
+                }
+                if queued.is_empty() {
+                    break;
                 }
-                Ok(paths) => {
-                    for path in paths {
-                        let meta = fs::metadata(&path)?;
-                        let modified = meta.modified()?;
-                        bins.insert(path.clone(), modified);
+                // Tasks that end up skipped have nothing to report either
+                for name in &queued {
+                    errors.remove(name);
+                }
+                last_rebuild = SystemTime::now();
+                cycle_changed = hooks::paths(&std::mem::take(&mut changed));
+                let mut next = Cycle::new(std::mem::take(&mut queued));
+                next.signals = std::mem::take(&mut reload);
+                next.after = std::mem::take(&mut after);
+                next.if_rebuilt = std::mem::take(&mut if_rebuilt);
+                cycle = Some(next);
+            }
+            let Some(current) = &mut cycle else {
+                break;
+            };
+            let ready = current.start_ready(&graph);
+            if ready.is_empty() {
+                break;
+            }
+
+            for name in ready {
+                let task = graph.task(&name);
+                let cmd = match task.kind {
+                    Kind::Service => {
+                        let runner = runners.get_mut(&name).unwrap();
+                        let built = built(&graph, &artifacts, &name);
+                        let up = matches!(
+                            service_status[&name],
+                            ServiceStatus::Starting
+                                | ServiceStatus::Running
+                                | ServiceStatus::NotReady { .. }
+                        );
+                        match current.signals.get(&name) {
+                            Some(signal) => {
+                                runner.signal(*signal);
+                                refresh = true;
+                            }
+                            None if current.if_rebuilt.contains(&name)
+                                && up
+                                && runner.runs(&built) =>
+                            {
+                                log!("Leaving {name:?} running, its binary is the same");
+                            }
+                            None => restart(
+                                &name,
+                                runner,
+                                built,
+                                gate.as_ref(),
+                                &mut service_status,
+                                &mut awaiting,
+                                &mut refresh,
+                            ),
+                        }
+                        current.finish(&graph, &name, true);
+                        succeeded.insert(name);
+                        continue;
                     }
-                    if args.command == Subcommand::Run {
-                        run_tx.send(()).unwrap();
+                    Kind::Test => {
+                        let mut cmd = tests.command(&config.test);
+                        task.configure(&mut cmd);
+                        cmd
                     }
-                }
+                    Kind::Command | Kind::Cargo => task.command(),
+                };
+                // The command is started once the pre-build hook allows it
+                let env = vec![
+                    ("WATCHF_TASK", name.clone()),
+                    ("WATCHF_CHANGED_PATHS", cycle_changed.clone()),
+                ];
+                build_id += 1;
+                config
+                    .hooks
+                    .spawn(Hook::PreBuild, env, build_id, tx.clone());
+                waiting.insert(build_id, Pending::Build { task: name, cmd });
+            }
+        }
+
+        if refresh && awaiting.is_empty() {
+            refresh = false;
+            if let Some(browsers) = &browsers {
+                browsers.reload();
             }
         }
 
-        if let Ok(res) = rx.recv() {
-            match res {
-                Ok(event) if !matches!(event.kind, EventKind::Remove(_)) => {
-                    for path in event.paths.iter() {
-                        // Files can be removed and subsequently recreated when
-                        // they're created by  editors. If the path doesn't
-                        // exist, that's fine.
-                        let Ok(meta) = fs::metadata(&path) else {
-                            continue;
+        // Without pending changes there's nothing to time out on
+        let msg = match changes.deadline(&config.debounce) {
+            None => Some(rx.recv()?),
+            Some(deadline) => {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                {
+                    Ok(msg) => Some(msg),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Err(RecvError.into()),
+                }
+            }
+        };
+        match msg {
+            Some(Message::Watch(Ok(event)))
+                if !paused && !matches!(event.kind, EventKind::Remove(_)) =>
+            {
+                for path in event.paths {
+                    if filter.is_ignored(&path, path.is_dir()) {
+                        continue;
+                    }
+                    // Files can be removed and subsequently recreated when
+                    // they're created by  editors. If the path doesn't
+                    // exist, that's fine.
+                    let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified())
+                    else {
+                        continue;
+                    };
+                    if modified > last_rebuild
+                        && (bins.is_empty() || bins.values().any(|b| modified > *b))
+                    {
+                        changes.insert(path);
+                    }
+                }
+            }
+            Some(Message::Watch(Err(e))) => log!("Watch error: {e}"),
+            // Results of cancelled tasks are stale
+            Some(Message::Built { id, outcome }) if running.contains_key(&id) => {
+                let name = running.remove(&id).unwrap().task;
+                let task = graph.task(&name);
+                // A successful task can't report anything new after a cargo task
+                // it depends on already did
+                let reported = cycle.as_ref().is_some_and(|c| {
+                    scope.iter().any(|dep| {
+                        graph.task(dep).kind == Kind::Cargo
+                            && c.succeeded(dep)
+                            && graph.depends_on(&name, dep)
+                    })
+                });
+                let ok = report(&name, task, &outcome, &config, !reported);
+                if task.kind != Kind::Command {
+                    errors.insert(name.clone(), outcome.diagnostics.clone());
+                }
+                let mut env = vec![
+                    ("WATCHF_TASK", name.clone()),
+                    (
+                        "WATCHF_DURATION_MS",
+                        outcome.duration.as_millis().to_string(),
+                    ),
+                ];
+                let hook = match &outcome.result {
+                    Ok(paths) if ok => {
+                        env.push(("WATCHF_ARTIFACTS", hooks::paths(paths)));
+                        Hook::PostBuild
+                    }
+                    result => {
+                        let status = match result {
+                            Ok(_) => "failed because of warnings".to_string(),
+                            Err(e) => e.to_string(),
                         };
-                        let modified = meta.modified()?;
-                        if modified > last_rebuild && bins.values().any(|b| modified > *b)
+                        env.push(("WATCHF_EXIT_STATUS", status));
+                        Hook::OnFailure
+                    }
+                };
+                config.hooks.spawn(hook, env, id, tx.clone());
+                waiting.insert(
+                    id,
+                    Pending::Finish {
+                        task: name,
+                        outcome,
+                        ok,
+                    },
+                );
+            }
+            // Hooks of cancelled tasks are stale as well
+            Some(Message::Hook { id, ok: allowed }) if waiting.contains_key(&id) => {
+                match waiting.remove(&id).unwrap() {
+                    Pending::Build { task: name, .. } if !allowed => {
+                        log!("Skipping task {name:?} because the pre-build hook failed");
+                        if let Some(cycle) = &mut cycle {
+                            cycle.finish(&graph, &name, false);
+                        }
+                    }
+                    Pending::Build { task: name, cmd } => {
+                        let json = graph.task(&name).kind != Kind::Command;
+                        match Build::spawn(cmd, &name, json, id, tx.clone()) {
+                            Ok(b) => {
+                                tui::send(Event::TaskStarted { name: name.clone() });
+                                task_status.insert(name, TaskStatus::Running);
+                                running.insert(b.id, b);
+                            }
+                            Err(e) => {
+                                log!("Task {name:?} failed: {e}");
+                                if let Some(cycle) = &mut cycle {
+                                    cycle.finish(&graph, &name, false);
+                                }
+                            }
+                        }
+                    }
+                    Pending::Finish {
+                        task: name,
+                        outcome,
+                        ok: built,
+                    } => {
+                        // The on-failure hook can't make a failed task succeed
+                        let vetoed = built && !allowed;
+                        if vetoed {
+                            log!(
+                                "Failing task {name:?} because the post-build hook failed"
+                            );
+                        }
+                        let ok = built && allowed;
+                        summarize(&name, graph.task(&name), &outcome, ok);
+                        let counts = Counts::of(&outcome.diagnostics);
+                        let duration_ms = outcome.duration.as_millis() as u64;
+                        task_status.insert(
+                            name.clone(),
+                            TaskStatus::Finished {
+                                ok,
+                                duration_ms,
+                                errors: counts.errors,
+                                warnings: counts.warnings,
+                            },
+                        );
+                        events::emit(Lifecycle::TaskFinished {
+                            task: name.clone(),
+                            ok,
+                            duration_ms,
+                            errors: counts.errors,
+                            warnings: counts.warnings,
+                        });
+                        tui::send(Event::TaskFinished {
+                            name: name.clone(),
+                            ok,
+                            duration: outcome.duration,
+                            counts,
+                        });
+                        if !ok
+                            && let Some(gate) = &gate
+                            && graph.depends_on(gate.service(), &name)
                         {
-                            rebuild = true;
-                            break;
+                            let details = match counts.errors {
+                                _ if vetoed => "The post-build hook failed".to_string(),
+                                0 => outcome.stderr.clone(),
+                                _ => diagnostics::render(
+                                    &outcome.diagnostics,
+                                    &config.diagnostics,
+                                ),
+                            };
+                            gate.fail(&name, &details);
+                        }
+
+                        if graph.task(&name).kind == Kind::Test
+                            && tests.finish(&outcome.tests, outcome.result.is_ok())
+                        {
+                            log!("Previously failing tests pass now, running all tests");
+                            queued.insert(name.clone());
+                        }
+                        if let Ok(paths) = outcome.result
+                            && !paths.is_empty()
+                        {
+                            for path in &paths {
+                                match fs::metadata(path).and_then(|m| m.modified()) {
+                                    Ok(modified) => {
+                                        bins.insert(path.clone(), modified);
+                                    }
+                                    Err(e) => {
+                                        log!("Failed to read {}: {e}", path.display())
+                                    }
+                                }
+                            }
+                            artifacts.insert(name.clone(), paths);
+                        }
+                        if ok {
+                            succeeded.insert(name.clone());
+                        }
+                        if let Some(cycle) = &mut cycle {
+                            cycle.finish(&graph, &name, ok);
                         }
                     }
                 }
-                Err(e) => eprintln!("Watch error: {e}"),
-                _ => {}
-            }
-        }
-    }
-}
-
-/// Build the executable and return the paths of the executable build artifacts
-fn build(config: &Config) -> eyre::Result<Vec<PathBuf>> {
-    use serde_json::Value;
-
-    #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
-    pub struct CompilerArtifact {
-        pub reason:        String,
-        pub package_id:    String,
-        pub manifest_path: String,
-        pub target:        Target,
-        pub features:      Vec<String>,
-        pub filenames:     Vec<String>,
-        pub executable:    Option<PathBuf>,
-        pub fresh:         bool,
-    }
-
-    #[derive(Default, Debug, Clone, PartialEq, Deserialize)]
-    pub struct Target {
-        pub kind:        Vec<String>,
-        pub crate_types: Vec<String>,
-        pub name:        String,
-        pub src_path:    String,
-        pub edition:     String,
-        pub doc:         bool,
-        pub doctest:     bool,
-        pub test:        bool,
-    }
-    eprintln!("Running build command: {:?}", config.build_cmd);
-    let cmd = Command::new(&config.build_cmd[0])
-        .args(&config.build_cmd[1..])
-        .args(["--message-format", "json"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()?;
-    let output = cmd.wait_with_output()?.exit_ok()?;
-    let stdout = String::from_utf8(output.stdout)?;
-
-    let artifacts = stdout
-        .lines()
-        .flat_map(|line| serde_json::de::from_str::<Value>(line).ok())
-        .filter(|v| {
-            v.get("reason")
-                .is_some_and(|reason| reason == "compiler-artifact")
-        })
-        .filter_map(|v| serde_json::from_value::<CompilerArtifact>(v.clone()).ok())
-        .filter(|artifact| {
-            artifact.executable.is_some()
-                && artifact.target.kind.iter().any(|x| x == "bin")
-        })
-        .filter_map(|artifact| artifact.executable)
-        .collect::<Vec<_>>();
-    eprintln!("Done running build command: {:?}", config.build_cmd);
-    Ok(artifacts)
+            }
+            // Respawns after a crash leave the proxy alone, it may be showing
+            // why a build failed
+            Some(Message::Spawned { service, result }) => {
+                let status = match &result {
+                    Ok(_) if services[&service].ready.is_some() => {
+                        ServiceStatus::Starting
+                    }
+                    Ok(_) => ServiceStatus::Running,
+                    Err(error) => ServiceStatus::Failed {
+                        error: error.clone(),
+                    },
+                };
+                service_status.insert(service.clone(), status);
+                if (result.is_err() || services[&service].ready.is_none())
+                    && awaiting.remove(&service)
+                {
+                    // Browsers stay on the page of a service that didn't restart
+                    if result.is_err() {
+                        refresh = false;
+                    }
+                    if let Some(gate) = &gate
+                        && gate.service() == service
+                    {
+                        gate.open();
+                    }
+                }
+            }
+            Some(Message::Ready { service, result }) => {
+                if awaiting.remove(&service) {
+                    if result.is_err() {
+                        refresh = false;
+                    }
+                    if let Some(gate) = &gate
+                        && gate.service() == service
+                    {
+                        gate.open();
+                    }
+                }
+                let status = match &result {
+                    Ok(_) => ServiceStatus::Running,
+                    Err(error) => ServiceStatus::NotReady {
+                        error: error.clone(),
+                    },
+                };
+                service_status.insert(service.clone(), status);
+                match result {
+                    Ok(after) => {
+                        log!("{service:?} is ready after {:.1}s", after.as_secs_f64());
+                        events::emit(Lifecycle::ServiceReady {
+                            service:  service.clone(),
+                            after_ms: after.as_millis() as u64,
+                        });
+                        tui::send(Event::ServiceReady { name: service });
+                    }
+                    Err(e) => log!("{service:?} failed to become ready: {e}"),
+                }
+            }
+            Some(Message::Exited { service, status }) => {
+                service_status.insert(service, status);
+            }
+            Some(Message::Key(Key::Rebuild)) => {
+                log!("Rebuilding everything");
+                rebuild = true;
+            }
+            Some(Message::Key(Key::Restart)) => {
+                for (name, runner) in &mut runners {
+                    restart(
+                        name,
+                        runner,
+                        built(&graph, &artifacts, name),
+                        gate.as_ref(),
+                        &mut service_status,
+                        &mut awaiting,
+                        &mut refresh,
+                    );
+                }
+            }
+            Some(Message::Key(Key::Clear)) => eprint!("\x1b[2J\x1b[3J\x1b[H"),
+            Some(Message::Key(Key::Pause)) => {
+                pause(!paused, &mut paused, &mut changes);
+            }
+            Some(Message::Key(Key::Kill)) => {
+                for runner in runners.values_mut() {
+                    runner.kill();
+                }
+            }
+            Some(Message::Control { request, reply }) => {
+                let response = match request {
+                    Request::Status => Response::Status(Status {
+                        paused,
+                        tasks: task_status.clone(),
+                        services: service_status.clone(),
+                    }),
+                    Request::Trigger { tasks } => {
+                        match tasks.iter().find(|t| !scope.contains(*t)) {
+                            Some(unknown) => Response::Error {
+                                message: format!("There is no task named {unknown:?}"),
+                            },
+                            None if tasks.is_empty() => {
+                                rebuild = true;
+                                Response::Ok
+                            }
+                            None => {
+                                queued.extend(
+                                    scope
+                                        .iter()
+                                        .filter(|name| {
+                                            tasks.iter().any(|t| {
+                                                *name == t || graph.depends_on(name, t)
+                                            })
+                                        })
+                                        .cloned(),
+                                );
+                                Response::Ok
+                            }
+                        }
+                    }
+                    Request::Restart { services } => {
+                        match services.iter().find(|s| !runners.contains_key(*s)) {
+                            Some(unknown) => Response::Error {
+                                message: format!("There is no service named {unknown:?}"),
+                            },
+                            None => {
+                                for (name, runner) in &mut runners {
+                                    if services.is_empty() || services.contains(name) {
+                                        restart(
+                                            name,
+                                            runner,
+                                            built(&graph, &artifacts, name),
+                                            gate.as_ref(),
+                                            &mut service_status,
+                                            &mut awaiting,
+                                            &mut refresh,
+                                        );
+                                    }
+                                }
+                                Response::Ok
+                            }
+                        }
+                    }
+                    Request::Pause => {
+                        pause(true, &mut paused, &mut changes);
+                        Response::Ok
+                    }
+                    Request::Resume => {
+                        pause(false, &mut paused, &mut changes);
+                        Response::Ok
+                    }
+                    // Answered by the control socket itself
+                    Request::Logs => Response::Ok,
+                };
+                let _ = reply.send(response);
+            }
+            Some(Message::Shutdown | Message::Key(Key::Quit)) => {
+                // Show what happens while shutting down
+                drop(tui.take());
+                log!("Shutting down...");
+                events::emit(Lifecycle::ShuttingDown);
+                for (_, b) in running.drain() {
+                    b.cancel();
+                }
+                if serving {
+                    let _ = fs::remove_file(&config.control_socket);
+                }
+                // Stop the services in parallel, each may take its stop timeout
+                std::thread::scope(|s| {
+                    for runner in std::mem::take(&mut runners).into_values() {
+                        s.spawn(move || drop(runner));
+                    }
+                });
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let batch = changes.take_ready(&config.debounce);
+        if let Some(batch) = &batch {
+            log!("Changes detected: {batch}");
+            events::emit(Lifecycle::ChangesDetected {
+                paths: batch.paths().map(Path::to_path_buf).collect(),
+            });
+            changed.extend(batch.paths().map(Path::to_path_buf));
+        }
+        if (batch.is_some() || rebuild)
+            && config.build_policy == BuildPolicy::Restart
+            && let Some(mut cycle) = cycle.take()
+        {
+            for (_, b) in running.drain() {
+                task_status.insert(b.task.clone(), TaskStatus::Cancelled);
+                b.cancel();
+            }
+            for (_, pending) in waiting.drain() {
+                let (Pending::Build { task, .. } | Pending::Finish { task, .. }) =
+                    pending;
+                task_status.insert(task, TaskStatus::Cancelled);
+            }
+            let signals = std::mem::take(&mut cycle.signals);
+            let waits = std::mem::take(&mut cycle.after);
+            let conditional = std::mem::take(&mut cycle.if_rebuilt);
+            let unfinished = cycle.unfinished();
+            for (name, signal) in signals {
+                if unfinished.contains(&name) {
+                    reload.entry(name).or_insert(signal);
+                }
+            }
+            for (name, tasks) in waits {
+                if unfinished.contains(&name) {
+                    after.entry(name).or_default().extend(tasks);
+                }
+            }
+            // An unconditional restart queued since takes precedence
+            for name in conditional {
+                if unfinished.contains(&name)
+                    && (!queued.contains(&name) || if_rebuilt.contains(&name))
+                {
+                    if_rebuilt.insert(name);
+                }
+            }
+            queued.extend(unfinished);
+        }
+        if std::mem::take(&mut rebuild) {
+            reload.clear();
+            after.clear();
+            if_rebuilt.clear();
+            queued.extend(scope.iter().cloned());
+        }
+
+        if let Some(batch) = batch {
+            let route = triggers.route(&batch, &running_services);
+            let mut dirty = affected(&graph, &route, &scope, &entry, &succeeded);
+            for name in &running_services {
+                // Services that never started have to be started regardless
+                let own = dirty.remove(name).then(|| {
+                    let service = &services[name];
+                    if succeeded.contains(name) {
+                        Action::new(service.on_change, service.signal)
+                    } else {
+                        Action::Restart
+                    }
+                });
+                let action = [own, route.services.get(name).copied()]
+                    .into_iter()
+                    .flatten()
+                    .reduce(Action::or);
+                match action {
+                    Some(Action::Restart) => {
+                        reload.remove(name);
+                        // A service running a build artifact is only restarted
+                        // for a rebuild of it, unless a rule or its own inputs
+                        // ask for it
+                        let forced = services[name].artifact.is_none()
+                            || !succeeded.contains(name)
+                            || route.services.get(name) == Some(&Action::Restart)
+                            || graph.inputs_match(name, &route.unmatched);
+                        if forced {
+                            if_rebuilt.remove(name);
+                        } else if !queued.contains(name) {
+                            if_rebuilt.insert(name.clone());
+                        }
+                        dirty.insert(name.clone());
+                    }
+                    // A queued restart takes precedence
+                    Some(Action::Signal(signal))
+                        if !queued.contains(name) || reload.contains_key(name) =>
+                    {
+                        reload.insert(name.clone(), signal);
+                        dirty.insert(name.clone());
+                    }
+                    _ => continue,
+                }
+                if let Some(tasks) = route.after.get(name) {
+                    after
+                        .entry(name.clone())
+                        .or_default()
+                        .extend(tasks.iter().cloned());
+                }
+            }
+            queued.extend(dirty);
+        }
+    }
+}
+
+/// The tasks `command` runs on its own: every service or its entry point, along
+/// with everything they depend on
+fn entry(
+    command: &Subcommand,
+    services: &BTreeMap<String, Service>,
+    graph: &Graph,
+) -> eyre::Result<BTreeSet<String>> {
+    let entry = match command {
+        // Run every service, along with the `run` task if it isn't one
+        Subcommand::Run { task: None } => services
+            .keys()
+            .map(String::as_str)
+            .chain(graph.contains("run").then_some("run"))
+            .flat_map(|name| graph.closure(name))
+            .collect(),
+        command => {
+            let entry = match command {
+                Subcommand::Build => "build",
+                Subcommand::Run { task } => task.as_deref().unwrap_or("run"),
+                Subcommand::Test => "test",
+                _ => unreachable!("client commands don't run tasks"),
+            };
+            eyre::ensure!(
+                graph.contains(entry),
+                "There is no task named {entry:?}, configure it in `[tasks.{entry}]` \
+                 or using the shorthands"
+            );
+            graph.closure(entry)
+        }
+    };
+    eyre::ensure!(!entry.is_empty(), "There are no services to run");
+    Ok(entry)
+}
+
+/// The tasks `command` runs: `entry`, and with a plain `watchf run` the tasks
+/// the trigger rules run as well
+fn scope(
+    command: &Subcommand,
+    entry: &BTreeSet<String>,
+    graph: &Graph,
+    triggers: &Triggers,
+) -> BTreeSet<String> {
+    let mut scope = entry.clone();
+    if *command == (Subcommand::Run { task: None }) {
+        scope.extend(triggers.tasks().flat_map(|name| graph.closure(name)));
+    }
+    scope
+}
+
+/// The tasks a batch of changes runs: those of the matching trigger rules and
+/// those in `entry` affected by the paths no rule applies to. Tasks only the
+/// rules run aren't affected by other paths.
+fn affected(
+    graph: &Graph,
+    route: &Route,
+    scope: &BTreeSet<String>,
+    entry: &BTreeSet<String>,
+    succeeded: &HashSet<String>,
+) -> BTreeSet<String> {
+    // Rules may name tasks that aren't part of this session
+    let mut dirty = route
+        .tasks
+        .intersection(scope)
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    if !route.unmatched.is_empty() {
+        dirty.extend(graph.dirty(entry, &route.unmatched, succeeded));
+    }
+    dirty
+}
+
+/// Restart `service` with `artifacts`. The proxy holds requests for it and
+/// browsers are reloaded once it's up again.
+fn restart(
+    service: &str,
+    runner: &mut Runner,
+    artifacts: Vec<PathBuf>,
+    gate: Option<&Gate>,
+    status: &mut BTreeMap<String, ServiceStatus>,
+    awaiting: &mut BTreeSet<String>,
+    refresh: &mut bool,
+) {
+    runner.restart(artifacts);
+    status.insert(service.to_string(), ServiceStatus::Starting);
+    awaiting.insert(service.to_string());
+    *refresh = true;
+    if let Some(gate) = gate
+        && gate.service() == service
+    {
+        gate.hold();
+    }
+}
+
+/// The executables built by the tasks `service` depends on
+fn built(
+    graph: &Graph,
+    artifacts: &BTreeMap<String, Vec<PathBuf>>,
+    service: &str,
+) -> Vec<PathBuf> {
+    graph
+        .closure(service)
+        .iter()
+        .filter_map(|task| artifacts.get(task))
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Stop or start reacting to changes
+fn pause(pause: bool, paused: &mut bool, changes: &mut ChangeSet) {
+    if pause == *paused {
+        return;
+    }
+    *paused = pause;
+    if pause {
+        // Whatever is pending would run right away otherwise
+        *changes = ChangeSet::default();
+        log!("Paused watching, press p to resume");
+    } else {
+        log!("Resumed watching");
+    }
+}
+
+/// Show what the command of a finished task reported and return whether it
+/// succeeded
+fn report(
+    name: &str,
+    task: &Task,
+    outcome: &Outcome,
+    config: &Config,
+    show_diagnostics: bool,
+) -> bool {
+    if task.kind == Kind::Command {
+        return outcome.result.is_ok();
+    }
+
+    let counts = Counts::of(&outcome.diagnostics);
+    if show_diagnostics || outcome.result.is_err() {
+        let rendered = diagnostics::render(&outcome.diagnostics, &config.diagnostics);
+        output::block(name, &rendered);
+    }
+    // Without diagnostics, cargo's own output is all there is
+    if let Err(e) = &outcome.result
+        && counts.errors == 0
+        && outcome.tests.is_empty()
+    {
+        output::block(name, &outcome.stderr);
+        log!("{e}");
+    }
+
+    outcome.result.is_ok() && (!task.deny_warnings || counts.warnings == 0)
+}
+
+/// Show how a finished task went once its hooks had their say on whether it
+/// succeeded
+fn summarize(name: &str, task: &Task, outcome: &Outcome, ok: bool) {
+    if task.kind == Kind::Command {
+        match &outcome.result {
+            Ok(_) if ok => log!(
+                "Task {name:?} finished in {:.2}s",
+                outcome.duration.as_secs_f64()
+            ),
+            // The veto is reported already
+            Ok(_) => {}
+            Err(e) => log!("Task {name:?} failed: {e}"),
+        }
+    } else if outcome.tests.is_empty() {
+        let counts = Counts::of(&outcome.diagnostics);
+        log!(
+            "{}",
+            diagnostics::summary(name, !ok, counts, outcome.duration)
+        );
+    } else {
+        output::block(name, &testing::report(&outcome.tests, outcome.duration));
+    }
+}
+
+/// An empty directory for the test called `name`. It's inside of the current
+/// directory, so patterns relative to it apply.
+#[cfg(test)]
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::current_dir()
+        .unwrap()
+        .join("target/watchf-tests")
+        .join(format!("{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn graph() -> Graph {
+        let task = |kind: Kind, depends_on: &[&str]| Task {
+            cmd: strings(&["true"]),
+            kind,
+            depends_on: strings(depends_on),
+            ..Task::default()
+        };
+        Graph::of([
+            ("build", task(Kind::Cargo, &[])),
+            ("run", task(Kind::Service, &["build"])),
+            ("schema", task(Kind::Command, &[])),
+            ("migrate", task(Kind::Command, &["schema"])),
+            ("bundle", task(Kind::Command, &[])),
+        ])
+        .unwrap()
+    }
+
+    fn triggers(graph: &Graph) -> Triggers {
+        let rule = Trigger {
+            paths:    strings(&["src/migrations/**"]),
+            tasks:    strings(&["migrate"]),
+            service:  triggers::ServiceAction::Restart,
+            signal:   triggers::default_signal(),
+            services: Vec::new(),
+        };
+        Triggers::new(&[rule], graph).unwrap()
+    }
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn runs_the_tasks_of_trigger_rules() {
+        let graph = graph();
+        let triggers = triggers(&graph);
+        let services = BTreeMap::from([("run".to_string(), Service::default())]);
+
+        let build = entry(&Subcommand::Build, &services, &graph).unwrap();
+        assert_eq!(
+            scope(&Subcommand::Build, &build, &graph, &triggers),
+            set(&["build"])
+        );
+        let run = Subcommand::Run { task: None };
+        let entry = entry(&run, &services, &graph).unwrap();
+        assert_eq!(entry, set(&["build", "run"]));
+        assert_eq!(
+            scope(&run, &entry, &graph, &triggers),
+            set(&["build", "migrate", "run", "schema"])
+        );
+    }
+
+    #[test]
+    fn only_rules_run_their_tasks() {
+        let graph = graph();
+        let triggers = triggers(&graph);
+        let services = BTreeMap::from([("run".to_string(), Service::default())]);
+        let run = Subcommand::Run { task: None };
+        let entry = entry(&run, &services, &graph).unwrap();
+        let scope = scope(&run, &entry, &graph, &triggers);
+        let succeeded = scope.iter().cloned().collect::<HashSet<_>>();
+        let affected = |path: &str| {
+            let mut changes = ChangeSet::default();
+            changes.insert(PathBuf::from(path));
+            let route = triggers.route(&changes, &strings(&["run"]));
+            affected(&graph, &route, &scope, &entry, &succeeded)
+        };
+
+        assert_eq!(affected("src/main.rs"), set(&["build", "run"]));
+        assert_eq!(affected("src/migrations/1.sql"), set(&["migrate"]));
+    }
+
+    #[test]
+    fn services_take_precedence_over_the_test_task() {
+        let config = toml::de::from_str::<Config>(
+            "watch = [\"src\"]\n\
+             build-cmd = [\"cargo\", \"build\"]\n\
+             [services.test]\n\
+             cmd = [\"./test-server\"]\n",
+        )
+        .unwrap();
+        let services = config.services().unwrap();
+        let tasks = config.tasks(&services).unwrap();
+        assert_eq!(tasks["test"].kind, Kind::Service);
+
+        let config = toml::de::from_str::<Config>("watch = [\"src\"]").unwrap();
+        let tasks = config.tasks(&BTreeMap::new()).unwrap();
+        assert_eq!(tasks["test"].kind, Kind::Test);
+    }
 }
@@ -0,0 +1,277 @@
+use crate::{
+    events,
+    ready::Matcher,
+    tui::{self, Event, Pane},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    process::Child,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The tag of watchf's own messages
+pub const WATCHF: &str = "watchf";
+
+/// Colors tags are picked from, by their name
+const COLORS: [&str; 6] = ["36", "33", "32", "35", "34", "31"];
+
+static SETTINGS: OnceLock<Output> = OnceLock::new();
+/// The length of the longest tag so far, which all tags are padded to
+static WIDTH: AtomicUsize = AtomicUsize::new(WATCHF.len());
+/// Receivers of a copy of every line, see [`subscribe`]
+static SUBSCRIBERS: Mutex<Vec<Sender<Line>>> = Mutex::new(Vec::new());
+
+/// Settings for how the output of watchf and its processes is shown
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Output {
+    /// Put the name of the process in front of every line of output
+    pub prefix:     bool,
+    /// Put the time in front of every line of output
+    pub timestamps: bool,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            prefix:     true,
+            timestamps: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A line of output from watchf or one of its processes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Line {
+    pub stream: Stream,
+    pub tag:    String,
+    pub text:   String,
+}
+
+/// Print a message from watchf itself, tagged like the output of the
+/// processes
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::output::line(
+            $crate::output::Stream::Stderr,
+            $crate::output::WATCHF,
+            &format!($($arg)*),
+        )
+    };
+}
+pub(crate) use log;
+
+/// Apply `settings` to all output from now on
+pub fn init(settings: Output) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// Receive a copy of every line of output from now on, until the receiver is
+/// dropped
+pub fn subscribe() -> Receiver<Line> {
+    let (tx, rx) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+/// Print one line of output from watchf or a task
+pub fn line(stream: Stream, tag: &str, text: &str) {
+    emit(Pane::Tasks, stream, tag, text);
+}
+
+/// Print one line of output from `tag`, or show it in `pane` of the dashboard.
+/// Whole lines are written at once, so lines from different processes never
+/// mix.
+fn emit(pane: Pane, stream: Stream, tag: &str, text: &str) {
+    SUBSCRIBERS.lock().unwrap().retain(|tx| {
+        tx.send(Line {
+            stream,
+            tag: tag.to_string(),
+            text: text.to_string(),
+        })
+        .is_ok()
+    });
+
+    let settings = SETTINGS.get_or_init(Output::default);
+    let time = settings.timestamps.then(timestamp);
+    let event = Event::Line {
+        pane,
+        tag: tag.to_string(),
+        text: match &time {
+            Some(time) => format!("{time} {text}"),
+            None => text.to_string(),
+        },
+    };
+    if tui::send(event) {
+        return;
+    }
+
+    let color = match stream {
+        Stream::Stdout => io::stdout().is_terminal(),
+        Stream::Stderr => io::stderr().is_terminal(),
+    };
+    let mut line = String::new();
+    if let Some(time) = time {
+        line.push_str(&paint(color, "2", &time));
+        line.push(' ');
+    }
+    if settings.prefix {
+        let width = WIDTH.fetch_max(tag.len(), Ordering::Relaxed).max(tag.len());
+        let padded = format!("{tag:<width$} |");
+        let style = if tag == WATCHF {
+            "1"
+        } else {
+            COLORS[color_index(tag)]
+        };
+        line.push_str(&paint(color, style, &padded));
+        line.push(' ');
+    }
+    line.push_str(text);
+    line.push('\n');
+
+    // Stdout is reserved for the events then
+    let _ = match stream {
+        Stream::Stdout if !events::on_stdout() => {
+            io::stdout().lock().write_all(line.as_bytes())
+        }
+        _ => io::stderr().lock().write_all(line.as_bytes()),
+    };
+}
+
+/// Print every line of `text` from `tag` to stderr
+pub fn block(tag: &str, text: &str) {
+    for text in text.lines() {
+        line(Stream::Stderr, tag, text);
+    }
+}
+
+/// Print the output of `child` line by line tagged with `tag`, stdout to
+/// stdout and stderr to stderr, or show it in `pane` of the dashboard. Every
+/// line is also passed to `matcher`. The streams have to be piped. Returns the
+/// threads forwarding them, which finish once the streams are closed.
+pub fn forward(
+    child: &mut Child,
+    tag: &str,
+    pane: Pane,
+    matcher: Option<Matcher>,
+) -> Vec<JoinHandle<()>> {
+    let mut threads = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        let tag = tag.to_string();
+        let matcher = matcher.clone();
+        threads.push(thread::spawn(move || {
+            each_line(stdout, |l| {
+                matcher.iter().for_each(|m| m.check(l));
+                emit(pane, Stream::Stdout, &tag, l);
+            });
+        }));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let tag = tag.to_string();
+        threads.push(thread::spawn(move || {
+            each_line(stderr, |l| {
+                matcher.iter().for_each(|m| m.check(l));
+                emit(pane, Stream::Stderr, &tag, l);
+            });
+        }));
+    }
+    threads
+}
+
+fn each_line(stream: impl Read, mut f: impl FnMut(&str)) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    // Programs don't necessarily write valid UTF-8
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        f(String::from_utf8_lossy(&line).trim_end_matches(['\n', '\r']));
+        line.clear();
+    }
+}
+
+/// The same color out of six for the same tag every time
+pub fn color_index(tag: &str) -> usize {
+    let hash = tag
+        .bytes()
+        .fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    hash % COLORS.len()
+}
+
+/// Remove the colors and hyperlinks meant for a plain terminal
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI sequences like colors end with a letter
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            // OSC sequences like hyperlinks end with BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next().is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// The local time like `14:03:27.512`
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() as libc::time_t;
+    // SAFETY: `tm` is plain old data that `localtime_r` fills in, and
+    // `localtime_r` is thread-safe
+    let tm = unsafe {
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&secs, &mut tm);
+        tm
+    };
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis()
+    )
+}
+
+/// Apply the SGR `style` to `text` if `color` is set
+pub(crate) fn paint(color: bool, style: &str, text: &str) -> String {
+    if color {
+        format!("\x1b[{style}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,199 @@
+use serde::Deserialize;
+use std::{
+    fmt, io,
+    os::unix::process::ExitStatusExt,
+    process::{Child, ExitStatus},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a stopping process is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Signals that can be referred to by name in the config
+const SIGNALS: [(&str, libc::c_int); 9] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// A Unix signal, written as e.g. `"SIGTERM"`, `"TERM"` or `"15"` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Signal(pub libc::c_int);
+
+impl Signal {
+    pub const HUP: Self = Self(libc::SIGHUP);
+    pub const KILL: Self = Self(libc::SIGKILL);
+    pub const TERM: Self = Self(libc::SIGTERM);
+
+    fn name(self) -> Option<&'static str> {
+        SIGNALS
+            .iter()
+            .find(|(_, number)| *number == self.0)
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Self::TERM
+    }
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(number) = s.parse() {
+            return Ok(Self(number));
+        }
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        SIGNALS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, number)| Self(*number))
+            .ok_or_else(|| format!("unknown signal {s:?}"))
+    }
+}
+
+impl TryFrom<String> for Signal {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "SIG{name}"),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
+
+/// How a process ended after being asked to stop
+pub struct Stopped {
+    pub status: ExitStatus,
+    /// Whether the process had to be killed because it didn't exit in time
+    pub killed: bool,
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.killed {
+            return f.write_str("didn't stop in time and was killed");
+        }
+        describe_exit(&self.status, f)
+    }
+}
+
+/// How a process exited on its own
+pub struct Exited(pub ExitStatus);
+
+impl fmt::Display for Exited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        describe_exit(&self.0, f)
+    }
+}
+
+/// Write a description like "exited with code 1" or "was terminated by
+/// SIGTERM" for `status`
+fn describe_exit(status: &ExitStatus, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(code) = status.code() {
+        return write!(f, "exited with code {code}");
+    }
+    match status.signal() {
+        Some(signal) => write!(f, "was terminated by {}", Signal(signal))?,
+        None => write!(f, "exited with {status}")?,
+    }
+    if status.core_dumped() {
+        f.write_str(" (core dumped)")?;
+    }
+    Ok(())
+}
+
+/// Send `signal` to every process in the process group led by `pid`
+pub fn signal_group(pid: u32, signal: Signal) -> io::Result<()> {
+    // SAFETY: `kill` has no memory safety preconditions
+    if unsafe { libc::kill(-(pid as libc::pid_t), signal.0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Ask the process group led by `child` to exit by sending it `stop_signal`,
+/// and kill whatever is left of it after `timeout`
+pub fn stop(
+    child: &mut Child,
+    stop_signal: Signal,
+    timeout: Duration,
+) -> io::Result<Stopped> {
+    let pid = child.id();
+    signal_group(pid, stop_signal)?;
+
+    // The leader may exit before the rest of its group, so wait for both
+    let deadline = Instant::now() + timeout;
+    let mut status = None;
+    while Instant::now() < deadline {
+        if status.is_none() {
+            status = child.try_wait()?;
+        }
+        if let Some(status) = status
+            && !group_alive(pid)
+        {
+            return Ok(Stopped {
+                status,
+                killed: false,
+            });
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    match signal_group(pid, Signal::KILL) {
+        Err(e) if e.raw_os_error() != Some(libc::ESRCH) => return Err(e),
+        _ => {}
+    }
+    Ok(Stopped {
+        status: match status {
+            Some(status) => status,
+            None => child.wait()?,
+        },
+        killed: true,
+    })
+}
+
+/// Whether any process is left in the process group led by `pid`
+fn group_alive(pid: u32) -> bool {
+    signal_group(pid, Signal(0)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signals() {
+        assert_eq!("TERM".parse(), Ok(Signal::TERM));
+        assert_eq!("SIGHUP".parse(), Ok(Signal::HUP));
+        assert_eq!("sigkill".parse(), Ok(Signal::KILL));
+        assert_eq!("10".parse(), Ok(Signal(10)));
+        assert!("SIGNOPE".parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn displays_signals() {
+        assert_eq!(Signal::TERM.to_string(), "SIGTERM");
+        assert_eq!(Signal(64).to_string(), "signal 64");
+    }
+}
@@ -0,0 +1,208 @@
+use crate::output::{self, log};
+use eyre::WrapErr;
+use serde::Deserialize;
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait between attempts to connect to the service
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a client may take to send its request headers when it's answered
+/// by the proxy itself
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings for a reverse proxy in front of a service, which holds requests
+/// while it restarts and shows the errors of a failed build
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Proxy {
+    /// The address the proxy listens on, like `127.0.0.1:3000`
+    pub listen:          String,
+    /// The address the service listens on, like `127.0.0.1:8080`
+    pub target:          String,
+    /// The service behind the proxy
+    #[serde(default = "default_service")]
+    pub service:         String,
+    /// Milliseconds a request is held for before giving up on the service
+    #[serde(default = "default_hold_timeout")]
+    pub hold_timeout_ms: u64,
+}
+
+fn default_service() -> String {
+    "run".to_string()
+}
+
+fn default_hold_timeout() -> u64 {
+    30_000
+}
+
+/// What the proxy does with new requests
+enum State {
+    /// Forward them to the service
+    Open,
+    /// Hold them until the service is up again
+    Holding,
+    /// Answer them with a page showing why the build failed
+    Failed { task: String, details: String },
+}
+
+/// The running proxy, which is told what the service is up to
+pub struct Gate {
+    service: String,
+    state:   Arc<(Mutex<State>, Condvar)>,
+}
+
+impl Gate {
+    pub fn spawn(config: &Proxy) -> eyre::Result<Self> {
+        let listener = TcpListener::bind(&config.listen)
+            .wrap_err_with(|| format!("Failed to listen on {}", config.listen))?;
+        log!(
+            "Proxying http://{} to {} at {}",
+            config.listen,
+            config.service,
+            config.target
+        );
+
+        let state = Arc::new((Mutex::new(State::Holding), Condvar::new()));
+        thread::spawn({
+            let state = state.clone();
+            let config = config.clone();
+            move || {
+                for client in listener.incoming().map_while(Result::ok) {
+                    let state = state.clone();
+                    let config = config.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(client, &config, &state) {
+                            log!("Proxy: {e}");
+                        }
+                    });
+                }
+            }
+        });
+        Ok(Self {
+            service: config.service.clone(),
+            state,
+        })
+    }
+
+    /// The service behind the proxy
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Hold new requests while the service restarts
+    pub fn hold(&self) {
+        self.set(State::Holding);
+    }
+
+    /// Forward the held and new requests to the service
+    pub fn open(&self) {
+        self.set(State::Open);
+    }
+
+    /// Answer requests with `details` on why `task` failed until the service
+    /// restarts
+    pub fn fail(&self, task: &str, details: &str) {
+        self.set(State::Failed {
+            task:    task.to_string(),
+            details: output::strip_ansi(details),
+        });
+    }
+
+    fn set(&self, state: State) {
+        let (lock, changed) = &*self.state;
+        *lock.lock().unwrap() = state;
+        changed.notify_all();
+    }
+}
+
+/// Forward the connection of `client` to the service once it's up, or answer
+/// it with the errors of the failed build
+fn serve(
+    mut client: TcpStream,
+    config: &Proxy,
+    state: &(Mutex<State>, Condvar),
+) -> io::Result<()> {
+    let hold = Duration::from_millis(config.hold_timeout_ms);
+    let deadline = Instant::now() + hold;
+    let (lock, changed) = state;
+    let (guard, _) = changed
+        .wait_timeout_while(lock.lock().unwrap(), hold, |s| matches!(s, State::Holding))
+        .unwrap();
+    if let State::Failed { task, details } = &*guard {
+        let page = page(&format!("Task {task:?} failed"), details);
+        drop(guard);
+        return respond(client, "500 Internal Server Error", &page);
+    }
+    drop(guard);
+
+    // The service may still be starting up even without holding
+    let upstream = loop {
+        match TcpStream::connect(&config.target) {
+            Ok(upstream) => break upstream,
+            Err(_) if Instant::now() < deadline => thread::sleep(RETRY_INTERVAL),
+            Err(e) => {
+                let page = page(
+                    "Service unavailable",
+                    &format!("{} isn't accepting connections: {e}", config.service),
+                );
+                return respond(client, "502 Bad Gateway", &page);
+            }
+        }
+    };
+
+    let mut upstream_writer = upstream.try_clone()?;
+    let mut client_reader = client.try_clone()?;
+    let requests = thread::spawn(move || {
+        let _ = io::copy(&mut client_reader, &mut upstream_writer);
+        let _ = upstream_writer.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut &upstream, &mut client);
+    let _ = client.shutdown(Shutdown::Both);
+    let _ = requests.join();
+    Ok(())
+}
+
+/// Read the request of `client` and answer it with `html`
+fn respond(mut client: TcpStream, status: &str, html: &str) -> io::Result<()> {
+    client.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // The request doesn't matter, but closing the connection before reading it
+    // could reset it before the response arrives
+    BufReader::new(client.try_clone()?)
+        .lines()
+        .map_while(Result::ok)
+        .take_while(|l| !l.is_empty())
+        .for_each(drop);
+    client.write_all(
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+             Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n\
+             {html}",
+            html.len()
+        )
+        .as_bytes(),
+    )
+}
+
+/// A page that shows `text` as is and reloads itself every few seconds
+fn page(title: &str, text: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"2\"><title>{title}</title></head>\n\
+         <body style=\"background:#1e1e1e;color:#ddd\"><h1>{title}</h1>\n\
+         <pre>{}</pre></body></html>\n",
+        escape(text),
+        title = escape(title),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,176 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use serde::Serialize;
+use std::{fs, path::Path};
+
+/// A diagnostic in the JSON error file
+#[derive(Debug, PartialEq, Serialize)]
+struct Entry<'a> {
+    file:    String,
+    line:    usize,
+    column:  usize,
+    level:   &'static str,
+    code:    Option<&'a str>,
+    message: &'a str,
+}
+
+/// Replace the configured error files with `diagnostics`, leaving them empty
+/// after a clean build. Diagnostics reported by several tasks are only written
+/// once.
+pub fn write<'a>(
+    diagnostics: impl IntoIterator<Item = &'a Diagnostic>,
+    config: &Diagnostics,
+) -> eyre::Result<()> {
+    let mut entries = Vec::new();
+    for entry in diagnostics
+        .into_iter()
+        .filter(|d| !d.is_summary())
+        .filter_map(|d| {
+            let span = d.primary_span()?;
+            Some(Entry {
+                file:    span.path.display().to_string(),
+                line:    span.line_start,
+                column:  span.column_start,
+                level:   d.level.as_str(),
+                code:    d.code.as_ref().map(|c| c.code.as_str()),
+                message: &d.message,
+            })
+        })
+    {
+        if !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    if let Some(path) = &config.errorfile {
+        // Vim's default errorformat reads these through `%f:%l:%c:%m`, with
+        // the level leading the message
+        let contents = entries
+            .iter()
+            .map(|e| {
+                let code = e.code.map(|c| format!("[{c}]")).unwrap_or_default();
+                let message = e.message.lines().next().unwrap_or_default();
+                format!(
+                    "{}:{}:{}: {}{code}: {message}\n",
+                    e.file, e.line, e.column, e.level
+                )
+            })
+            .collect::<String>();
+        replace(path, &contents)?;
+    }
+    if let Some(path) = &config.errorfile_json {
+        replace(path, &serde_json::to_string_pretty(&entries)?)?;
+    }
+    Ok(())
+}
+
+/// Atomically replace the contents of `path`, so editors never read a partially
+/// written file
+fn replace(path: &Path, contents: &str) -> eyre::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Code;
+    use regex::Regex;
+
+    #[test]
+    fn errorfile() {
+        let dir = crate::scratch_dir("quickfix");
+        let config = Diagnostics {
+            errorfile: Some(dir.join("errors.txt")),
+            errorfile_json: Some(dir.join("errors.json")),
+            ..Diagnostics::default()
+        };
+
+        let mut mismatched = Diagnostic::new(
+            "error",
+            "mismatched types\nexpected `u32`",
+            Some("src/main.rs"),
+        );
+        mismatched.code = Some(Code {
+            code: "E0308".into(),
+        });
+        let mut diagnostics = [
+            mismatched,
+            Diagnostic::new("warning", "unused variable: `x`", Some("src/lib.rs")),
+            Diagnostic::new("error", "aborting due to 1 previous error", None),
+            Diagnostic::new("error", "linking with `cc` failed", None),
+        ];
+        for diagnostic in &mut diagnostics {
+            diagnostic.resolve(&dir);
+        }
+        // The same diagnostic from another task is only written once
+        write(diagnostics.iter().chain(&diagnostics[..1]), &config).unwrap();
+
+        let text = fs::read_to_string(dir.join("errors.txt")).unwrap();
+        let main = dir.join("src/main.rs");
+        let lib = dir.join("src/lib.rs");
+        assert_eq!(
+            text,
+            format!(
+                "{}:3:5: error[E0308]: mismatched types\n\
+                 {}:3:5: warning: unused variable: `x`\n",
+                main.display(),
+                lib.display(),
+            )
+        );
+
+        // What vim's `%f:%l:%c:%m` makes of each line
+        let errorformat = Regex::new(r"^(.+?):(\d+):(\d+):(.*)$").unwrap();
+        let main = main.display().to_string();
+        let lib = lib.display().to_string();
+        let parsed = text
+            .lines()
+            .map(|line| {
+                let c = errorformat.captures(line).unwrap();
+                [1, 2, 3, 4].map(|i| c.get(i).unwrap().as_str())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            [
+                [main.as_str(), "3", "5", " error[E0308]: mismatched types"],
+                [lib.as_str(), "3", "5", " warning: unused variable: `x`"],
+            ]
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("errors.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "file": main,
+                    "line": 3,
+                    "column": 5,
+                    "level": "error",
+                    "code": "E0308",
+                    "message": "mismatched types\nexpected `u32`",
+                },
+                {
+                    "file": lib,
+                    "line": 3,
+                    "column": 5,
+                    "level": "warning",
+                    "code": null,
+                    "message": "unused variable: `x`",
+                },
+            ])
+        );
+
+        // A clean build empties the files instead of leaving stale entries
+        write([], &config).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("errors.txt")).unwrap(), "");
+        assert_eq!(fs::read_to_string(dir.join("errors.json")).unwrap(), "[]");
+    }
+}
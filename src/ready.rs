@@ -0,0 +1,280 @@
+use eyre::{bail, ensure};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a single connection attempt of a probe may take
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+/// How long an HTTP probe waits for the response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Settings for deciding when a service is ready. Every configured check has
+/// to pass.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Ready {
+    /// An address like `127.0.0.1:8080` that has to accept connections. It
+    /// can't be one of the service's `sockets`, which watchf accepts
+    /// connections on even before the service starts.
+    pub tcp:        Option<String>,
+    /// A URL like `http://localhost:8080/health` that has to answer a GET
+    /// request with a 2xx status
+    pub http:       Option<String>,
+    /// A regex that has to match a line of output
+    pub output:     Option<String>,
+    /// A file that has to appear. It's removed before the service starts.
+    pub file:       Option<PathBuf>,
+    /// Milliseconds after which the service is considered to have failed
+    pub timeout_ms: u64,
+}
+
+impl Default for Ready {
+    fn default() -> Self {
+        Self {
+            tcp:        None,
+            http:       None,
+            output:     None,
+            file:       None,
+            timeout_ms: 30_000,
+        }
+    }
+}
+
+/// The readiness checks of a service, ready to be used
+pub struct Readiness {
+    config: Ready,
+    /// The host and port, and the path of `http`
+    http:   Option<(String, String)>,
+    output: Option<Regex>,
+}
+
+/// Looks for the line of output a service is ready after
+#[derive(Clone)]
+pub struct Matcher {
+    regex:   Regex,
+    matched: Arc<AtomicBool>,
+}
+
+/// The readiness checks of a single run of a service
+pub struct Probe {
+    started: Instant,
+    matched: Option<Arc<AtomicBool>>,
+}
+
+impl Readiness {
+    /// The checks of a service that gets listening `sockets` passed
+    pub fn new(config: &Ready, sockets: &[String]) -> eyre::Result<Self> {
+        ensure!(
+            config.tcp.is_some()
+                || config.http.is_some()
+                || config.output.is_some()
+                || config.file.is_some(),
+            "`ready` needs at least one of `tcp`, `http`, `output` or `file`"
+        );
+        if let Some(tcp) = &config.tcp {
+            let probed = tcp.to_socket_addrs()?.collect::<Vec<_>>();
+            // A socket listening on all interfaces takes the connections to
+            // every address with its port
+            let owned = sockets
+                .iter()
+                .filter_map(|s| s.to_socket_addrs().ok())
+                .flatten()
+                .any(|socket| {
+                    probed.iter().any(|addr| {
+                        addr.port() == socket.port()
+                            && (socket.ip().is_unspecified() || addr.ip() == socket.ip())
+                    })
+                });
+            ensure!(
+                !owned,
+                "`ready.tcp` can't check {tcp}, watchf listens on it for the service, \
+                 use `http` or `output` instead"
+            );
+        }
+        let http = match &config.http {
+            Some(url) => {
+                let Some(rest) = url.strip_prefix("http://") else {
+                    bail!("Only http:// URLs can be probed, not {url:?}");
+                };
+                let (authority, path) = match rest.find('/') {
+                    Some(i) => rest.split_at(i),
+                    None => (rest, "/"),
+                };
+                ensure!(!authority.is_empty(), "{url:?} has no host to probe");
+                // The colons of an IPv6 address are inside of brackets
+                let port = authority
+                    .rsplit_once(':')
+                    .is_some_and(|(_, port)| !port.ends_with(']'));
+                let authority = match port {
+                    true => authority.to_string(),
+                    false => format!("{authority}:80"),
+                };
+                Some((authority, path.to_string()))
+            }
+            None => None,
+        };
+        Ok(Self {
+            config: config.clone(),
+            http,
+            output: config.output.as_deref().map(Regex::new).transpose()?,
+        })
+    }
+
+    /// Start checking a service that's about to be spawned. Its output has to
+    /// be passed through the returned [`Matcher`].
+    pub fn probe(&self) -> (Probe, Option<Matcher>) {
+        if let Some(file) = &self.config.file {
+            let _ = fs::remove_file(file);
+        }
+        let matcher = self.output.clone().map(|regex| Matcher {
+            regex,
+            matched: Arc::default(),
+        });
+        let probe = Probe {
+            started: Instant::now(),
+            matched: matcher.as_ref().map(|m| m.matched.clone()),
+        };
+        (probe, matcher)
+    }
+}
+
+impl Matcher {
+    pub fn check(&self, line: &str) {
+        if !self.matched.load(Ordering::Relaxed) && self.regex.is_match(line) {
+            self.matched.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Probe {
+    /// How long the service took to become ready, `None` if it isn't ready yet,
+    /// or why it failed
+    pub fn poll(&self, readiness: &Readiness) -> Result<Option<Duration>, String> {
+        let config = &readiness.config;
+        let ready = self
+            .matched
+            .as_ref()
+            .is_none_or(|m| m.load(Ordering::Relaxed))
+            && config.file.as_ref().is_none_or(|f| f.exists())
+            && config
+                .tcp
+                .as_deref()
+                .is_none_or(|addr| connect(addr).is_some())
+            && readiness
+                .http
+                .as_ref()
+                .is_none_or(|(authority, path)| http_ok(authority, path));
+        let elapsed = self.started.elapsed();
+        if ready {
+            Ok(Some(elapsed))
+        } else if elapsed >= Duration::from_millis(config.timeout_ms) {
+            Err(format!("not ready after {:.1}s", elapsed.as_secs_f64()))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Connect to the first address `addr` resolves to that accepts the
+/// connection, like `localhost` may be `::1` or `127.0.0.1`
+fn connect(addr: impl ToSocketAddrs) -> Option<TcpStream> {
+    addr.to_socket_addrs()
+        .ok()?
+        .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok())
+}
+
+/// Whether a GET request for `path` is answered with a 2xx status
+fn http_ok(authority: &str, path: &str) -> bool {
+    let Some(mut stream) = connect(authority) else {
+        return false;
+    };
+    let _ = stream.set_read_timeout(Some(RESPONSE_TIMEOUT));
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {authority}\r\n\r\n");
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+    let mut status = String::new();
+    let _ = BufReader::new(stream).read_line(&mut status);
+    // Like `HTTP/1.1 200 OK`
+    status
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.len() == 3 && code.starts_with('2'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(addr: &str) -> Ready {
+        Ready {
+            tcp: Some(addr.to_string()),
+            ..Ready::default()
+        }
+    }
+
+    fn http(url: &str) -> eyre::Result<(String, String)> {
+        let config = Ready {
+            http: Some(url.to_string()),
+            ..Ready::default()
+        };
+        Ok(Readiness::new(&config, &[])?.http.unwrap())
+    }
+
+    #[test]
+    fn rejects_addresses_watchf_listens_on() {
+        let sockets = ["0.0.0.0:8080".to_string(), "127.0.0.1:9090".to_string()];
+        // Listening on all interfaces takes every address with the port
+        assert!(Readiness::new(&tcp("127.0.0.1:8080"), &sockets).is_err());
+        assert!(Readiness::new(&tcp("127.0.0.1:9090"), &sockets).is_err());
+        assert!(Readiness::new(&tcp("127.0.0.2:9090"), &sockets).is_ok());
+        assert!(Readiness::new(&tcp("127.0.0.1:8081"), &sockets).is_ok());
+        assert!(Readiness::new(&tcp("127.0.0.1:8080"), &[]).is_ok());
+    }
+
+    #[test]
+    fn http_urls() {
+        assert_eq!(
+            http("http://127.0.0.1:8080/health?full").unwrap(),
+            ("127.0.0.1:8080".to_string(), "/health?full".to_string())
+        );
+        assert_eq!(
+            http("http://localhost:8080").unwrap(),
+            ("localhost:8080".to_string(), "/".to_string())
+        );
+        // Without a port, HTTP's default one
+        assert_eq!(
+            http("http://localhost/health").unwrap(),
+            ("localhost:80".to_string(), "/health".to_string())
+        );
+        assert_eq!(http("http://[::1]").unwrap().0, "[::1]:80");
+        assert_eq!(http("http://[::1]:8080").unwrap().0, "[::1]:8080");
+        assert!(http("http:///health").is_err());
+        assert!(http("https://localhost:8443/health").is_err());
+        assert!(http("localhost:8080/health").is_err());
+
+        // `localhost` may resolve to an address nothing listens on first
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        assert!(connect(closed).is_none());
+        assert!(connect(&[closed, open][..]).is_some());
+    }
+
+    #[test]
+    fn needs_a_check() {
+        assert!(Readiness::new(&Ready::default(), &[]).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Settings for falling back to the last good binary when a new one crashes
+/// right after starting
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Rollback {
+    /// Milliseconds a new binary has to stay up for to count as good
+    pub startup_ms: u64,
+    /// The number of binaries that are kept around
+    pub keep:       usize,
+    /// Where the binaries are kept
+    pub dir:        PathBuf,
+}
+
+impl Default for Rollback {
+    fn default() -> Self {
+        Self {
+            startup_ms: 5000,
+            keep:       3,
+            dir:        PathBuf::from("target/watchf/archive"),
+        }
+    }
+}
+
+/// Copies of the binaries that were run, which the next build can't overwrite
+pub struct Archive {
+    dir:     PathBuf,
+    keep:    usize,
+    entries: VecDeque<PathBuf>,
+    /// Numbers the copies, so each one gets a new path
+    next:    u64,
+}
+
+impl Archive {
+    pub fn new(rollback: &Rollback, service: &str) -> Self {
+        Self {
+            dir:     rollback.dir.join(service),
+            keep:    rollback.keep.max(1),
+            entries: VecDeque::new(),
+            next:    0,
+        }
+    }
+
+    /// Copy `bin` into the archive and return the path of the copy. The oldest
+    /// copies are removed, except for `good`.
+    pub fn store(&mut self, bin: &Path, good: Option<&Path>) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let name = bin.file_name().unwrap_or_default().to_string_lossy();
+        let copy = self.dir.join(format!("{}-{name}", self.next));
+        self.next += 1;
+        // Left over from an earlier session
+        let _ = fs::remove_file(&copy);
+        fs::copy(bin, &copy)?;
+        self.entries.push_back(copy.clone());
+
+        while self.entries.len() > self.keep {
+            let Some(i) = self
+                .entries
+                .iter()
+                .position(|e| Some(e.as_path()) != good && *e != copy)
+            else {
+                break;
+            };
+            if let Some(old) = self.entries.remove(i) {
+                let _ = fs::remove_file(old);
+            }
+        }
+        Ok(copy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store() {
+        let dir = crate::scratch_dir("rollback");
+        let bin = dir.join("app");
+        let rollback = Rollback {
+            keep: 2,
+            dir: dir.join("archive"),
+            ..Rollback::default()
+        };
+        let mut archive = Archive::new(&rollback, "api");
+
+        fs::write(&bin, "0").unwrap();
+        let first = archive.store(&bin, None).unwrap();
+        assert_eq!(first, dir.join("archive/api/0-app"));
+        // The copy stays intact when the build replaces the binary
+        fs::write(&bin, "1").unwrap();
+        assert_eq!(fs::read_to_string(&first).unwrap(), "0");
+
+        // `good` outlives newer copies that never proved themselves
+        let second = archive.store(&bin, Some(&first)).unwrap();
+        let third = archive.store(&bin, Some(&first)).unwrap();
+        assert!(first.exists());
+        assert!(!second.exists());
+        assert!(third.exists());
+        assert_eq!(archive.entries, [first.clone(), third.clone()]);
+
+        // Once a newer copy is good, the oldest ones go
+        let fourth = archive.store(&bin, Some(&third)).unwrap();
+        assert!(!first.exists());
+        assert_eq!(archive.entries, [third.clone(), fourth.clone()]);
+        let fifth = archive.store(&bin, None).unwrap();
+        assert!(!third.exists());
+        assert_eq!(archive.entries, [fourth, fifth]);
+    }
+
+    #[test]
+    fn keeps_the_new_copy() {
+        let dir = crate::scratch_dir("rollback-keep");
+        let bin = dir.join("app");
+        fs::write(&bin, "").unwrap();
+        let rollback = Rollback {
+            keep: 0,
+            dir: dir.join("archive"),
+            ..Rollback::default()
+        };
+        let mut archive = Archive::new(&rollback, "api");
+
+        let first = archive.store(&bin, None).unwrap();
+        let second = archive.store(&bin, Some(&first)).unwrap();
+        // `keep` is at least one, but `good` is never removed
+        assert_eq!(archive.entries, [first.clone(), second.clone()]);
+        let third = archive.store(&bin, Some(&second)).unwrap();
+        assert!(!first.exists());
+        assert_eq!(archive.entries, [second, third]);
+    }
+}
@@ -0,0 +1,738 @@
+use crate::{
+    Config, Message,
+    control::ServiceStatus,
+    events::{self, Lifecycle},
+    hooks::{Hook, Hooks},
+    output::{self, log},
+    process::{self, Exited, Signal},
+    ready::{Probe, Readiness, Ready},
+    rollback::{Archive, Rollback},
+    sockets::Sockets,
+    triggers::{self, ServiceAction},
+    tui::{self, Event, Pane},
+};
+use eyre::{bail, eyre};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the program is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the program has to stay up for its crashes to be forgotten
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+
+/// Settings for running a freshly built binary instead of the run command
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Artifact {
+    /// Name of the binary to run when the build produces several
+    pub bin:      Option<String>,
+    /// Arguments to pass to the binary
+    pub args:     Vec<String>,
+    /// Fall back to the last good binary when a new one crashes right after
+    /// starting
+    pub rollback: Option<Rollback>,
+}
+
+/// A long-running program supervised alongside the tasks
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Service {
+    /// The command to run, unless `artifact` is set
+    pub cmd:        Vec<String>,
+    /// Run the binary from the build artifacts instead of `cmd`
+    pub artifact:   Option<Artifact>,
+    /// The working directory of the service
+    pub cwd:        Option<PathBuf>,
+    /// Extra environment variables for the service
+    pub env:        BTreeMap<String, String>,
+    /// Tasks that have to succeed before the service (re)starts
+    pub depends_on: Vec<String>,
+    /// Gitignore-style globs of the paths the service depends on. Without any,
+    /// every change does.
+    pub inputs:     Vec<String>,
+    /// What happens to the service when its inputs or dependencies change. A
+    /// service running a build artifact is only restarted for its dependencies
+    /// if the binary it runs was rebuilt.
+    pub on_change:  ServiceAction,
+    /// The signal sent with `on-change = "signal"`
+    pub signal:     Signal,
+    /// The tag in front of every line of output. Defaults to the name of the
+    /// service.
+    pub prefix:     Option<String>,
+    /// What happens when the service exits on its own
+    pub restart:    Restart,
+    /// How to tell that the service is ready after starting
+    pub ready:      Option<Ready>,
+    /// Addresses like `127.0.0.1:8080` that watchf listens on and passes to
+    /// the service with `LISTEN_FDS`, so they stay bound across restarts
+    pub sockets:    Vec<String>,
+
+    /// Overrides `stop-signal` for this service
+    pub stop_signal:     Option<Signal>,
+    /// Overrides `stop-timeout-ms` for this service
+    pub stop_timeout_ms: Option<u64>,
+}
+
+impl Default for Service {
+    fn default() -> Self {
+        Self {
+            cmd:             Vec::new(),
+            artifact:        None,
+            cwd:             None,
+            env:             BTreeMap::new(),
+            depends_on:      Vec::new(),
+            inputs:          Vec::new(),
+            on_change:       ServiceAction::default(),
+            signal:          triggers::default_signal(),
+            prefix:          None,
+            restart:         Restart::default(),
+            ready:           None,
+            sockets:         Vec::new(),
+            stop_signal:     None,
+            stop_timeout_ms: None,
+        }
+    }
+}
+
+/// How a program that exited on its own is restarted
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Restart {
+    pub policy:         RestartPolicy,
+    /// Milliseconds to wait before restarting, doubled with every crash in a
+    /// row
+    pub backoff_ms:     u64,
+    /// The longest the backoff grows
+    pub max_backoff_ms: u64,
+    /// Stop restarting after this many crashes in a row
+    pub max_crashes:    u32,
+}
+
+impl Default for Restart {
+    fn default() -> Self {
+        Self {
+            policy:         RestartPolicy::default(),
+            backoff_ms:     500,
+            max_backoff_ms: 30_000,
+            max_crashes:    5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave it stopped until the next change
+    #[default]
+    Never,
+    /// Restart it if it exited with an error or was killed by a signal
+    OnFailure,
+    /// Restart it however it exited
+    Always,
+}
+
+/// Read the services of a Procfile, made of lines like `web: ./server --port
+/// $PORT`
+pub fn procfile(path: &Path) -> eyre::Result<BTreeMap<String, Service>> {
+    let contents = fs::read_to_string(path)?;
+    let mut services = BTreeMap::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, cmd)) = line
+            .split_once(':')
+            .filter(|(name, _)| !name.trim().is_empty())
+        else {
+            bail!("Invalid line in {}: {line:?}", path.display());
+        };
+        let service = Service {
+            cmd: ["sh", "-c", cmd.trim()].map(String::from).to_vec(),
+            ..Service::default()
+        };
+        services.insert(name.trim().to_string(), service);
+    }
+    Ok(services)
+}
+
+/// The part of the config the run thread needs
+struct Settings {
+    name:         String,
+    service:      Service,
+    readiness:    Option<Readiness>,
+    sockets:      Sockets,
+    stop_signal:  Signal,
+    stop_timeout: Duration,
+    hooks:        Hooks,
+    /// Receives [`Message::Spawned`], [`Message::Ready`] and
+    /// [`Message::Exited`]
+    tx:           Sender<Message>,
+}
+
+/// What the run thread is asked to do with the program
+enum Request {
+    /// (Re)start it using the executables from the latest build
+    Restart(Vec<PathBuf>),
+    /// Send it a signal
+    Signal(Signal),
+    /// Stop it until the next restart
+    Kill,
+}
+
+/// Handle to the thread that owns the running program. The program is stopped
+/// when it's dropped.
+pub struct Runner {
+    tx:       Sender<Request>,
+    handle:   Option<JoinHandle<()>>,
+    artifact: Option<Artifact>,
+    /// The binary the program was last restarted with, and when it was built
+    launched: Option<(PathBuf, SystemTime)>,
+}
+
+impl Runner {
+    /// Supervise the service called `name`, reporting when it's spawned, ready
+    /// and exits to `main_tx`
+    pub fn spawn(
+        name: &str,
+        service: &Service,
+        config: &Config,
+        main_tx: Sender<Message>,
+    ) -> eyre::Result<Self> {
+        let settings = Settings {
+            name:         name.to_string(),
+            service:      service.clone(),
+            readiness:    service
+                .ready
+                .as_ref()
+                .map(|ready| Readiness::new(ready, &service.sockets))
+                .transpose()?,
+            sockets:      Sockets::bind(&service.sockets)?,
+            stop_signal:  service.stop_signal.unwrap_or(config.stop_signal),
+            stop_timeout: Duration::from_millis(
+                service.stop_timeout_ms.unwrap_or(config.stop_timeout_ms),
+            ),
+            hooks:        config.hooks.clone(),
+            tx:           main_tx,
+        };
+        let (tx, rx) = mpsc::channel();
+        let handle = std::thread::spawn(move || run(&settings, rx));
+        Ok(Self {
+            tx,
+            handle: Some(handle),
+            artifact: service.artifact.clone(),
+            launched: None,
+        })
+    }
+
+    /// (Re)start the program after a successful build that produced the
+    /// executables in `artifacts`
+    pub fn restart(&mut self, artifacts: Vec<PathBuf>) {
+        self.launched = self.binary(&artifacts);
+        // The thread only exits once the channel is closed
+        self.tx.send(Request::Restart(artifacts)).unwrap();
+    }
+
+    /// Whether the program was last restarted with the same build of the binary
+    /// out of `artifacts` it would be restarted with now
+    pub fn runs(&self, artifacts: &[PathBuf]) -> bool {
+        self.launched.is_some() && self.launched == self.binary(artifacts)
+    }
+
+    /// The binary out of `artifacts` the program runs and when it was built,
+    /// if it runs a build artifact
+    fn binary(&self, artifacts: &[PathBuf]) -> Option<(PathBuf, SystemTime)> {
+        let artifact = self.artifact.as_ref()?;
+        let bin = select(artifacts, artifact.bin.as_deref()).ok()?;
+        let modified = fs::metadata(bin).and_then(|m| m.modified()).ok()?;
+        Some((bin.to_path_buf(), modified))
+    }
+
+    /// Send `signal` to the program, if it's running
+    pub fn signal(&self, signal: Signal) {
+        self.tx.send(Request::Signal(signal)).unwrap();
+    }
+
+    /// Stop the program until it's restarted
+    pub fn kill(&mut self) {
+        self.launched = None;
+        self.tx.send(Request::Kill).unwrap();
+    }
+}
+
+impl Drop for Runner {
+    /// Stop the program and wait for it to exit
+    fn drop(&mut self) {
+        // The thread stops the program once the channel is closed
+        let (closed, _) = mpsc::channel();
+        drop(std::mem::replace(&mut self.tx, closed));
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(settings: &Settings, rx: Receiver<Request>) {
+    let archive = settings
+        .service
+        .artifact
+        .as_ref()
+        .and_then(|a| a.rollback.as_ref())
+        .map(|rollback| Archive::new(rollback, &settings.name));
+    let mut supervisor = Supervisor {
+        settings,
+        prog: None,
+        started: Instant::now(),
+        bin: None,
+        probe: None,
+        archive,
+        good: None,
+        confirmed: false,
+        crashes: 0,
+        respawn_at: None,
+    };
+
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Request::Restart(artifacts)) => {
+                supervisor.crashes = 0;
+                supervisor.respawn_at = None;
+                supervisor.stop();
+                match supervisor.resolve(&artifacts) {
+                    Ok(()) => supervisor.spawn(),
+                    Err(e) => supervisor.failed(&e),
+                }
+            }
+            Ok(Request::Signal(signal)) => {
+                if let Some(p) = &supervisor.prog {
+                    log!("Sending {signal} to child with pid {}", p.id());
+                    if let Err(e) = process::signal_group(p.id(), signal) {
+                        log!("Failed to signal child with pid {}: {e}", p.id());
+                    }
+                }
+            }
+            Ok(Request::Kill) => {
+                supervisor.respawn_at = None;
+                supervisor.stop();
+                supervisor.report(ServiceStatus::Stopped);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        supervisor.check();
+    }
+
+    supervisor.stop();
+}
+
+/// Keeps the program running according to its restart policy
+struct Supervisor<'a> {
+    settings:   &'a Settings,
+    prog:       Option<Child>,
+    /// When `prog` was spawned
+    started:    Instant,
+    /// The binary to run, if the service runs a build artifact
+    bin:        Option<PathBuf>,
+    /// Whether the program is ready yet, until it is
+    probe:      Option<Probe>,
+    /// Copies of the binaries, if rolling back is enabled
+    archive:    Option<Archive>,
+    /// The latest binary that stayed up for the startup time
+    good:       Option<PathBuf>,
+    /// Whether `bin` stayed up for the startup time
+    confirmed:  bool,
+    /// How often the program exited in a row without staying up for
+    /// [`STABLE_AFTER`]
+    crashes:    u32,
+    /// When to restart the program after it exited
+    respawn_at: Option<Instant>,
+}
+
+impl<'a> Supervisor<'a> {
+    fn rollback(&self) -> Option<&'a Rollback> {
+        self.settings.service.artifact.as_ref()?.rollback.as_ref()
+    }
+
+    /// Pick the binary to run out of `artifacts`, and archive it in case it has
+    /// to be rolled back to later
+    fn resolve(&mut self, artifacts: &[PathBuf]) -> eyre::Result<()> {
+        let Some(artifact) = &self.settings.service.artifact else {
+            return Ok(());
+        };
+        let bin = select(artifacts, artifact.bin.as_deref())?;
+        self.bin = Some(match &mut self.archive {
+            Some(archive) => archive.store(bin, self.good.as_deref())?,
+            None => bin.to_path_buf(),
+        });
+        self.confirmed = false;
+        Ok(())
+    }
+
+    fn spawn(&mut self) {
+        let settings = self.settings;
+        let mut env = vec![("WATCHF_SERVICE", settings.name.clone())];
+        if let Some(bin) = &self.bin {
+            env.push(("WATCHF_BIN", bin.display().to_string()));
+        }
+        if !settings.hooks.run(Hook::PreRun, &env) {
+            self.failed(&eyre!("the pre-run hook failed"));
+            return;
+        }
+        let (probe, matcher) = settings.readiness.as_ref().map(Readiness::probe).unzip();
+        match command(settings, self.bin.as_deref()).and_then(|mut cmd| Ok(cmd.spawn()?))
+        {
+            Ok(mut p) => {
+                let tag = settings.service.prefix.as_ref().unwrap_or(&settings.name);
+                output::forward(&mut p, tag, Pane::Services, matcher.flatten());
+                tui::send(Event::ServiceStarted {
+                    name: settings.name.clone(),
+                    pid:  p.id(),
+                });
+                events::emit(Lifecycle::ProcessSpawned {
+                    service: settings.name.clone(),
+                    pid:     p.id(),
+                });
+                let _ = settings.tx.send(Message::Spawned {
+                    service: settings.name.clone(),
+                    result:  Ok(p.id()),
+                });
+                self.started = Instant::now();
+                self.prog = Some(p);
+                self.probe = probe;
+            }
+            Err(e) => self.failed(&e),
+        }
+    }
+
+    /// Tell the main loop whether the program became ready
+    fn ready(&self, result: Result<Duration, String>) {
+        let _ = self.settings.tx.send(Message::Ready {
+            service: self.settings.name.clone(),
+            result,
+        });
+    }
+
+    /// Tell the main loop what became of the program after it stopped
+    fn report(&self, status: ServiceStatus) {
+        let _ = self.settings.tx.send(Message::Exited {
+            service: self.settings.name.clone(),
+            status,
+        });
+    }
+
+    fn failed(&self, e: &eyre::Report) {
+        log!("Failed to run {:?}: {e}", self.settings.name);
+        tui::send(Event::ServiceStopped {
+            name:   self.settings.name.clone(),
+            status: format!("failed to start: {e}"),
+        });
+        let _ = self.settings.tx.send(Message::Spawned {
+            service: self.settings.name.clone(),
+            result:  Err(e.to_string()),
+        });
+    }
+
+    fn stop(&mut self) {
+        self.probe = None;
+        if let Some(mut p) = self.prog.take() {
+            stop(&mut p, self.settings);
+        }
+    }
+
+    /// Report it if the program exited on its own, and restart it when it's
+    /// time to
+    fn check(&mut self) {
+        if let Some(p) = &mut self.prog
+            && let Ok(Some(status)) = p.try_wait()
+        {
+            let pid = p.id();
+            self.prog = None;
+            self.exited(pid, status);
+        }
+        if let (Some(probe), Some(readiness)) = (&self.probe, &self.settings.readiness) {
+            match probe.poll(readiness) {
+                Ok(None) => {}
+                Ok(Some(after)) => {
+                    self.probe = None;
+                    self.ready(Ok(after));
+                }
+                Err(e) => {
+                    self.probe = None;
+                    self.ready(Err(e));
+                }
+            }
+        }
+        if let Some(rollback) = self.rollback()
+            && self.prog.is_some()
+            && !self.confirmed
+            && self.started.elapsed() >= Duration::from_millis(rollback.startup_ms)
+        {
+            self.confirmed = true;
+            self.good = self.bin.clone();
+        }
+        if self.respawn_at.is_some_and(|at| at <= Instant::now()) {
+            self.respawn_at = None;
+            self.spawn();
+        }
+    }
+
+    fn exited(&mut self, pid: u32, status: ExitStatus) {
+        let settings = self.settings;
+        let restart = &settings.service.restart;
+        log!(
+            "{:?} with pid {pid} {} after {:.2}s",
+            settings.name,
+            Exited(status),
+            self.started.elapsed().as_secs_f64()
+        );
+        tui::send(Event::ServiceStopped {
+            name:   settings.name.clone(),
+            status: Exited(status).to_string(),
+        });
+        events::emit(Lifecycle::ProcessExited {
+            service: settings.name.clone(),
+            pid,
+            status: Exited(status).to_string(),
+            code: status.code(),
+            signal: status.signal(),
+            stopped: false,
+        });
+        if self.probe.take().is_some() {
+            self.ready(Err("exited before it was ready".to_string()));
+        }
+        // Whatever the program spawned would keep its ports and files busy
+        let _ = process::signal_group(pid, Signal::KILL);
+
+        let description = Exited(status).to_string();
+        let env = [
+            ("WATCHF_SERVICE", settings.name.clone()),
+            ("WATCHF_PID", pid.to_string()),
+            ("WATCHF_EXIT_STATUS", description.clone()),
+        ];
+        if !settings.hooks.run(Hook::PostExit, &env) {
+            log!(
+                "Not restarting {:?} because the post-exit hook failed",
+                settings.name
+            );
+            self.report(ServiceStatus::Exited {
+                status: description,
+            });
+            return;
+        }
+
+        if let Some(rollback) = self.rollback()
+            && !status.success()
+            && self.started.elapsed() < Duration::from_millis(rollback.startup_ms)
+            && let Some(good) = self.good.clone()
+            && self.bin.as_ref() != Some(&good)
+        {
+            let line = "!".repeat(72);
+            log!("{line}");
+            log!(
+                "{:?} crashed right after starting, rolling back to the last good binary",
+                settings.name
+            );
+            log!(
+                "RUNNING STALE CODE from an earlier build: {}",
+                good.display()
+            );
+            log!("{line}");
+            self.report(ServiceStatus::Crashed {
+                status: description,
+            });
+            self.bin = Some(good);
+            self.spawn();
+            return;
+        }
+
+        let applies = match restart.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !status.success(),
+            RestartPolicy::Always => true,
+        };
+        if !applies {
+            self.report(ServiceStatus::Exited {
+                status: description,
+            });
+            return;
+        }
+        if self.started.elapsed() >= STABLE_AFTER {
+            self.crashes = 0;
+        }
+        self.crashes += 1;
+        if self.crashes >= restart.max_crashes {
+            log!(
+                "{:?} exited {} times in a row, not restarting it until the next change",
+                settings.name,
+                self.crashes
+            );
+            self.report(ServiceStatus::GaveUp {
+                status: description,
+            });
+            return;
+        }
+
+        let backoff = restart
+            .backoff_ms
+            .saturating_mul(1 << (self.crashes - 1).min(16))
+            .min(restart.max_backoff_ms);
+        log!(
+            "Restarting {:?} in {:.1}s ({}/{})",
+            settings.name,
+            backoff as f64 / 1000.0,
+            self.crashes,
+            restart.max_crashes
+        );
+        self.respawn_at = Some(Instant::now() + Duration::from_millis(backoff));
+        self.report(ServiceStatus::Crashed {
+            status: description,
+        });
+    }
+}
+
+/// Create the command for the program in a process group of its own
+fn command(settings: &Settings, bin: Option<&Path>) -> eyre::Result<Command> {
+    let service = &settings.service;
+    let mut cmd = match &service.artifact {
+        Some(artifact) => {
+            let bin = bin.ok_or_else(|| eyre!("The build produced no binaries"))?;
+            log!("Running {} {:?}", bin.display(), artifact.args);
+            settings.sockets.command(bin, &artifact.args)
+        }
+        None => {
+            log!("Running {}: {:?}", settings.name, service.cmd);
+            settings.sockets.command(&service.cmd[0], &service.cmd[1..])
+        }
+    };
+    cmd.envs(&service.env);
+    if let Some(cwd) = &service.cwd {
+        cmd.current_dir(cwd);
+    }
+    // Reading the terminal from a background process group would stop it, and
+    // the keys are meant for watchf
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    cmd.process_group(0);
+    Ok(cmd)
+}
+
+/// Pick the binary named `bin` out of `artifacts`, or the only one there is
+fn select<'a>(artifacts: &'a [PathBuf], bin: Option<&str>) -> eyre::Result<&'a Path> {
+    if let Some(bin) = bin {
+        return artifacts
+            .iter()
+            .find(|a| a.file_stem() == Some(OsStr::new(bin)))
+            .map(PathBuf::as_path)
+            .ok_or_else(|| eyre!("The build produced no binary named {bin:?}"));
+    }
+    match artifacts {
+        [] => bail!("The build produced no binaries"),
+        [artifact] => Ok(artifact.as_path()),
+        _ => {
+            let names = artifacts
+                .iter()
+                .filter_map(|a| a.file_stem()?.to_str())
+                .collect::<Vec<_>>();
+            bail!("The build produced several binaries {names:?}, select one with `bin`")
+        }
+    }
+}
+
+/// Stop the program along with everything it spawned and report how it exited
+fn stop(prog: &mut Child, settings: &Settings) {
+    log!(
+        "Stopping child with pid {} using {}...",
+        prog.id(),
+        settings.stop_signal
+    );
+    let status = match process::stop(prog, settings.stop_signal, settings.stop_timeout) {
+        Ok(stopped) => {
+            log!("Child with pid {} {stopped}", prog.id());
+            events::emit(Lifecycle::ProcessExited {
+                service: settings.name.clone(),
+                pid:     prog.id(),
+                status:  stopped.to_string(),
+                code:    stopped.status.code(),
+                signal:  stopped.status.signal(),
+                stopped: true,
+            });
+            stopped.to_string()
+        }
+        Err(e) => {
+            log!("Failed to stop child with pid {}: {e}", prog.id());
+            format!("failed to stop: {e}")
+        }
+    };
+    // It's restarted or shut down regardless
+    let env = [
+        ("WATCHF_SERVICE", settings.name.clone()),
+        ("WATCHF_PID", prog.id().to_string()),
+        ("WATCHF_EXIT_STATUS", status.clone()),
+    ];
+    settings.hooks.run(Hook::PostExit, &env);
+    tui::send(Event::ServiceStopped {
+        name: settings.name.clone(),
+        status,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Procfile with `contents` in a scratch directory of its own
+    fn write(name: &str, contents: &str) -> PathBuf {
+        let path = crate::scratch_dir(name).join("Procfile");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_procfiles() {
+        let path = write(
+            "procfile",
+            "# The API\nweb: ./server --port $PORT\n\nworker:python worker.py\n",
+        );
+        let services = procfile(&path).unwrap();
+
+        assert_eq!(services.keys().collect::<Vec<_>>(), ["web", "worker"]);
+        assert_eq!(services["web"].cmd, ["sh", "-c", "./server --port $PORT"]);
+        assert_eq!(services["worker"].cmd, ["sh", "-c", "python worker.py"]);
+    }
+
+    #[test]
+    fn rejects_invalid_procfile_lines() {
+        let path = write("procfile-invalid", "web ./server\n");
+        assert!(procfile(&path).is_err());
+        let path = write("procfile-unnamed", "web: ./server\n : ./worker\n");
+        assert!(procfile(&path).is_err());
+    }
+
+    #[test]
+    fn selects_the_binary() {
+        let one = [PathBuf::from("target/debug/api")];
+        let several = ["target/debug/api", "target/debug/worker"].map(PathBuf::from);
+        let cases: [(&[PathBuf], Option<&str>, Option<&str>); 6] = [
+            (&[], None, None),
+            (&one, None, Some("target/debug/api")),
+            (&one, Some("worker"), None),
+            (&several, None, None),
+            (&several, Some("worker"), Some("target/debug/worker")),
+            (&several, Some("web"), None),
+        ];
+        for (artifacts, bin, expected) in cases {
+            let selected = select(artifacts, bin).ok();
+            assert_eq!(selected, expected.map(Path::new), "{artifacts:?} {bin:?}");
+        }
+    }
+}
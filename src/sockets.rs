@@ -0,0 +1,81 @@
+use eyre::WrapErr;
+use std::{
+    ffi::OsStr,
+    io,
+    net::TcpListener,
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    process::Command,
+};
+
+/// The first file descriptor passed with the `LISTEN_FDS` convention
+const FIRST_FD: i32 = 3;
+
+/// Listening sockets that stay open across restarts of a service and are
+/// passed to each of its processes, so connections queue up in the backlog
+/// instead of being refused while it restarts
+pub struct Sockets(Vec<TcpListener>);
+
+impl Sockets {
+    /// Listen on `addresses` like `127.0.0.1:8080`
+    pub fn bind(addresses: &[String]) -> eyre::Result<Self> {
+        addresses
+            .iter()
+            .map(|address| {
+                TcpListener::bind(address)
+                    .wrap_err_with(|| format!("Failed to listen on {address}"))
+            })
+            .collect::<eyre::Result<_>>()
+            .map(Self)
+    }
+
+    /// A command running `program` with `args` that inherits the sockets as
+    /// file descriptors 3 and up, with `LISTEN_FDS` and `LISTEN_PID` set like
+    /// systemd's socket activation does
+    pub fn command(
+        &self,
+        program: impl AsRef<OsStr>,
+        args: &[impl AsRef<OsStr>],
+    ) -> Command {
+        if self.0.is_empty() {
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            return cmd;
+        }
+
+        // The pid of the program isn't known before it's forked, so a shell
+        // sets `LISTEN_PID` to its own and then replaces itself with it
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", r#"LISTEN_PID=$$ exec "$0" "$@""#])
+            .arg(program)
+            .args(args)
+            .env("LISTEN_FDS", self.0.len().to_string())
+            .env_remove("LISTEN_FDNAMES");
+
+        let fds = self.0.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+        let mut moved = vec![-1; fds.len()];
+        // SAFETY: Only async-signal-safe functions are called in the child and
+        // nothing is allocated there
+        unsafe {
+            cmd.pre_exec(move || {
+                // Move every socket above the target range first, so none of
+                // them is overwritten before it's been moved into place
+                let above = FIRST_FD + fds.len() as i32;
+                for (fd, moved) in fds.iter().zip(&mut moved) {
+                    *moved = libc::fcntl(*fd, libc::F_DUPFD, above);
+                    if *moved < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                // `dup2` clears the close-on-exec flag of the new descriptor
+                for (target, fd) in (FIRST_FD..).zip(&moved) {
+                    if libc::dup2(*fd, target) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    libc::close(*fd);
+                }
+                Ok(())
+            });
+        }
+        cmd
+    }
+}
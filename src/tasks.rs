@@ -0,0 +1,429 @@
+use crate::{debounce::ChangeSet, filter::Globs, output::log, process::Signal};
+use eyre::{bail, ensure};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    path::PathBuf,
+    process::Command,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// Any command, with its output shown as is
+    #[default]
+    Command,
+    /// A cargo command whose JSON messages are turned into diagnostics and
+    /// build artifacts
+    Cargo,
+    /// The tests of the `test` subcommand
+    #[serde(skip)]
+    Test,
+    /// The long-running program, restarted once the tasks it depends on
+    /// succeeded
+    #[serde(skip)]
+    Service,
+}
+
+/// A named step that runs whenever its inputs or dependencies change
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Task {
+    pub cmd:           Vec<String>,
+    pub kind:          Kind,
+    /// The working directory of the command
+    pub cwd:           Option<PathBuf>,
+    /// Extra environment variables for the command
+    pub env:           BTreeMap<String, String>,
+    /// Tasks that have to finish successfully before this one runs
+    pub depends_on:    Vec<String>,
+    /// Gitignore-style globs of the paths this task depends on. Without any,
+    /// every change does.
+    pub inputs:        Vec<String>,
+    /// Treat warnings of a `cargo` task as a failure
+    pub deny_warnings: bool,
+}
+
+impl Task {
+    /// The command of this task, in its working directory and environment
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.cmd[0]);
+        cmd.args(&self.cmd[1..]);
+        if self.kind == Kind::Cargo {
+            cmd.args(["--message-format", "json"]);
+        }
+        self.configure(&mut cmd);
+        cmd
+    }
+
+    /// Apply the working directory and environment of this task to `cmd`
+    pub fn configure(&self, cmd: &mut Command) {
+        cmd.envs(&self.env);
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+    }
+}
+
+/// The tasks and their dependencies on each other
+pub struct Graph {
+    tasks:  BTreeMap<String, Task>,
+    inputs: BTreeMap<String, Globs>,
+}
+
+impl Graph {
+    pub fn new(tasks: BTreeMap<String, Task>) -> eyre::Result<Self> {
+        let mut inputs = BTreeMap::new();
+        for (name, task) in &tasks {
+            ensure!(
+                !task.cmd.is_empty() || matches!(task.kind, Kind::Test | Kind::Service),
+                "Task {name:?} has no `cmd`"
+            );
+            for dep in &task.depends_on {
+                ensure!(
+                    tasks.contains_key(dep),
+                    "Task {name:?} depends on unknown task {dep:?}"
+                );
+            }
+            inputs.insert(name.clone(), Globs::new(&task.inputs)?);
+        }
+
+        let graph = Self { tasks, inputs };
+        for name in graph.tasks.keys() {
+            graph.check_cycles(name, &mut Vec::new())?;
+        }
+        Ok(graph)
+    }
+
+    fn check_cycles<'a>(
+        &'a self,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+    ) -> eyre::Result<()> {
+        if path.contains(&name) {
+            bail!(
+                "Tasks depend on each other: {} -> {name}",
+                path.join(" -> ")
+            );
+        }
+        path.push(name);
+        for dep in &self.tasks[name].depends_on {
+            self.check_cycles(dep, path)?;
+        }
+        path.pop();
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tasks.contains_key(name)
+    }
+
+    /// The task called `name`, which has to exist
+    pub fn task(&self, name: &str) -> &Task {
+        &self.tasks[name]
+    }
+
+    /// `entry` and everything it depends on, directly or indirectly
+    pub fn closure(&self, entry: &str) -> BTreeSet<String> {
+        let mut scope = BTreeSet::new();
+        let mut stack = vec![entry];
+        while let Some(name) = stack.pop() {
+            if scope.insert(name.to_string()) {
+                stack.extend(self.tasks[name].depends_on.iter().map(String::as_str));
+            }
+        }
+        scope
+    }
+
+    /// Whether `name` has inputs of its own and some of `changes` match them
+    pub fn inputs_match(&self, name: &str, changes: &ChangeSet) -> bool {
+        let inputs = &self.inputs[name];
+        !inputs.is_empty() && changes.paths().any(|p| inputs.matches(p))
+    }
+
+    /// Whether `name` depends on `dep`, directly or indirectly
+    pub fn depends_on(&self, name: &str, dep: &str) -> bool {
+        name != dep && self.closure(name).contains(dep)
+    }
+
+    /// The tasks in `scope` that have to run after `changes`: those with
+    /// matching inputs, those that never succeeded and everything depending on
+    /// them
+    pub fn dirty(
+        &self,
+        scope: &BTreeSet<String>,
+        changes: &ChangeSet,
+        succeeded: &HashSet<String>,
+    ) -> BTreeSet<String> {
+        let mut dirty = scope
+            .iter()
+            .filter(|name| {
+                let inputs = &self.inputs[name.as_str()];
+                !succeeded.contains(name.as_str())
+                    || inputs.is_empty()
+                    || changes.paths().any(|p| inputs.matches(p))
+            })
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        loop {
+            let before = dirty.len();
+            for name in scope {
+                if self.tasks[name]
+                    .depends_on
+                    .iter()
+                    .any(|d| dirty.contains(d))
+                {
+                    dirty.insert(name.clone());
+                }
+            }
+            if dirty.len() == before {
+                return dirty;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Graph {
+    /// A graph of `tasks`, given along with their names
+    pub fn of<const N: usize>(tasks: [(&str, Task); N]) -> eyre::Result<Self> {
+        Self::new(tasks.map(|(name, task)| (name.to_string(), task)).into())
+    }
+}
+
+/// One pass through the task graph, running every task of it once its
+/// dependencies finished successfully
+#[derive(Debug, Default)]
+pub struct Cycle {
+    pending: BTreeSet<String>,
+    running: BTreeSet<String>,
+    /// Tasks that finished successfully
+    done:    BTreeSet<String>,
+    /// Tasks that failed or were skipped because a dependency failed
+    failed:  BTreeSet<String>,
+
+    /// Services that are signalled instead of restarted
+    pub signals:    BTreeMap<String, Signal>,
+    /// Tasks run by trigger rules that services wait for, in addition to
+    /// their dependencies
+    pub after:      BTreeMap<String, BTreeSet<String>>,
+    /// Services running a build artifact that are only restarted if it changed
+    pub if_rebuilt: BTreeSet<String>,
+}
+
+impl Cycle {
+    pub fn new(tasks: BTreeSet<String>) -> Self {
+        Self {
+            pending: tasks,
+            ..Self::default()
+        }
+    }
+
+    /// The tasks `name` waits for: everything it depends on, directly or
+    /// indirectly, and the tasks triggered along with it
+    fn prerequisites(&self, graph: &Graph, name: &str) -> BTreeSet<String> {
+        let mut prerequisites = graph.closure(name);
+        prerequisites.remove(name);
+        prerequisites.extend(self.after.get(name).into_iter().flatten().cloned());
+        prerequisites
+    }
+
+    /// Mark the pending tasks without unfinished prerequisites as running and
+    /// return them
+    pub fn start_ready(&mut self, graph: &Graph) -> Vec<String> {
+        let unfinished =
+            |name: &String| self.pending.contains(name) || self.running.contains(name);
+        let ready = self
+            .pending
+            .iter()
+            .filter(|name| !self.prerequisites(graph, name).iter().any(unfinished))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in &ready {
+            self.pending.remove(name);
+            self.running.insert(name.clone());
+        }
+        ready
+    }
+
+    /// Record the result of a running task, skipping everything waiting for it
+    /// if it failed
+    pub fn finish(&mut self, graph: &Graph, name: &str, ok: bool) {
+        self.running.remove(name);
+        if ok {
+            self.done.insert(name.to_string());
+            return;
+        }
+
+        self.failed.insert(name.to_string());
+        loop {
+            let blocked = self
+                .pending
+                .iter()
+                .filter(|n| !self.prerequisites(graph, n).is_disjoint(&self.failed))
+                .cloned()
+                .collect::<Vec<_>>();
+            if blocked.is_empty() {
+                return;
+            }
+            for name in blocked {
+                log!("Skipping task {name:?} because a dependency failed");
+                self.pending.remove(&name);
+                self.failed.insert(name);
+            }
+        }
+    }
+
+    /// Whether `name` finished successfully in this cycle
+    pub fn succeeded(&self, name: &str) -> bool {
+        self.done.contains(name)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.pending.is_empty() && self.running.is_empty()
+    }
+
+    /// The tasks that haven't finished, to be carried over into the next cycle
+    pub fn unfinished(self) -> BTreeSet<String> {
+        self.pending.into_iter().chain(self.running).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(kind: Kind, depends_on: &[&str], inputs: &[&str]) -> Task {
+        Task {
+            cmd: vec!["true".to_string()],
+            kind,
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            inputs: inputs.iter().map(|i| i.to_string()).collect(),
+            ..Task::default()
+        }
+    }
+
+    fn names(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// `check` <- `build` <- `run`, `check` <- `test` and `assets` <- `web`
+    fn graph() -> Graph {
+        let tasks = [
+            ("check", task(Kind::Cargo, &[], &["*.rs"])),
+            ("build", task(Kind::Cargo, &["check"], &["*.rs"])),
+            ("test", task(Kind::Test, &["check"], &["*.rs"])),
+            ("assets", task(Kind::Command, &[], &["assets/**"])),
+            ("run", task(Kind::Service, &["build"], &["Cargo.toml"])),
+            ("web", task(Kind::Service, &["assets"], &["Cargo.toml"])),
+        ];
+        Graph::of(tasks).unwrap()
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let tasks = [
+            ("a", task(Kind::Command, &["b"], &[])),
+            ("b", task(Kind::Command, &["c"], &[])),
+            ("c", task(Kind::Command, &["a"], &[])),
+        ];
+        let Err(e) = Graph::of(tasks) else {
+            panic!("the cycle wasn't detected");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Tasks depend on each other: a -> b -> c -> a"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_dependencies() {
+        let tasks = [("a", task(Kind::Command, &["b"], &[]))];
+        assert!(Graph::of(tasks).is_err());
+    }
+
+    #[test]
+    fn closure() {
+        let graph = graph();
+        assert_eq!(graph.closure("run"), names(&["build", "check", "run"]));
+        assert!(graph.depends_on("run", "check"));
+        assert!(!graph.depends_on("run", "assets"));
+        assert!(!graph.depends_on("run", "run"));
+    }
+
+    #[test]
+    fn inputs_match() {
+        let graph = Graph::of([
+            ("run", task(Kind::Service, &[], &["Cargo.toml"])),
+            ("api", task(Kind::Service, &[], &[])),
+        ])
+        .unwrap();
+        let mut changes = ChangeSet::default();
+        changes.insert(PathBuf::from("src/main.rs"));
+        assert!(!graph.inputs_match("run", &changes));
+        // Without inputs of its own, no change is specific to it
+        assert!(!graph.inputs_match("api", &changes));
+        changes.insert(PathBuf::from("Cargo.toml"));
+        assert!(graph.inputs_match("run", &changes));
+    }
+
+    #[test]
+    fn dirty_propagates_to_dependents() {
+        let graph = graph();
+        let scope = graph.tasks.keys().cloned().collect();
+        let mut succeeded = graph.tasks.keys().cloned().collect::<HashSet<_>>();
+        let mut changes = ChangeSet::default();
+        changes.insert(PathBuf::from("src/lib.rs"));
+
+        let dirty = graph.dirty(&scope, &changes, &succeeded);
+        assert_eq!(dirty, names(&["build", "check", "run", "test"]));
+
+        // Tasks that never succeeded run regardless of their inputs
+        succeeded.remove("assets");
+        let dirty = graph.dirty(&scope, &changes, &succeeded);
+        assert_eq!(
+            dirty,
+            names(&["assets", "build", "check", "run", "test", "web"])
+        );
+    }
+
+    #[test]
+    fn skips_only_what_depends_on_a_failed_task() {
+        let graph = graph();
+        let mut cycle = Cycle::new(graph.tasks.keys().cloned().collect());
+        assert_eq!(cycle.start_ready(&graph), ["assets", "check"]);
+
+        cycle.finish(&graph, "check", false);
+        assert!(cycle.start_ready(&graph).is_empty());
+        cycle.finish(&graph, "assets", true);
+        assert_eq!(cycle.start_ready(&graph), ["web"]);
+        cycle.finish(&graph, "web", true);
+
+        assert!(cycle.is_finished());
+        assert!(cycle.succeeded("web"));
+        assert!(!cycle.succeeded("run"));
+        assert!(cycle.failed.contains("build") && cycle.failed.contains("test"));
+    }
+
+    #[test]
+    fn services_wait_for_their_own_dependencies() {
+        let graph = graph();
+        let mut cycle = Cycle::new(names(&["assets", "check", "web"]));
+        assert_eq!(cycle.start_ready(&graph), ["assets", "check"]);
+        cycle.finish(&graph, "assets", true);
+        // `check` is still running, but `web` doesn't depend on it
+        assert_eq!(cycle.start_ready(&graph), ["web"]);
+    }
+
+    #[test]
+    fn services_wait_for_triggered_tasks() {
+        let graph = graph();
+        let mut cycle = Cycle::new(names(&["check", "web"]));
+        cycle.after.insert("web".to_string(), names(&["check"]));
+        assert_eq!(cycle.start_ready(&graph), ["check"]);
+        cycle.finish(&graph, "check", false);
+        assert!(cycle.start_ready(&graph).is_empty());
+        assert!(cycle.is_finished());
+        assert!(!cycle.succeeded("web"));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::output::{log, paint};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    fmt::Write,
+    io::IsTerminal,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// Settings for the `test` subcommand
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Test {
+    pub runner:        TestRunner,
+    /// Extra arguments for the test command. Arguments after `--` are passed
+    /// to the test binaries.
+    pub args:          Vec<String>,
+    /// Rerun the tests that failed last time on their own before running all
+    /// tests
+    pub failed_first:  bool,
+    /// Unlock libtest's JSON output on stable toolchains through
+    /// `RUSTC_BOOTSTRAP`. That reaches rustc as well, so the tests may then use
+    /// unstable features `cargo build` rejects.
+    pub unstable_json: bool,
+}
+
+impl Default for Test {
+    fn default() -> Self {
+        Self {
+            runner:        TestRunner::default(),
+            args:          Vec::new(),
+            failed_first:  true,
+            unstable_json: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TestRunner {
+    /// `cargo test`, with libtest's unstable JSON output on nightly toolchains
+    /// and its plain output otherwise
+    #[default]
+    Cargo,
+    /// `cargo nextest run`
+    Nextest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// The result of a single test
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name:      String,
+    pub status:    Status,
+    /// What the test printed, only reported for failed tests
+    pub output:    Option<String>,
+    pub exec_time: Option<f64>,
+}
+
+impl TestResult {
+    /// Parse a libtest JSON event, if it's the result of a test
+    pub fn parse(event: Value) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Event {
+            #[serde(rename = "type")]
+            kind:      String,
+            event:     String,
+            name:      String,
+            stdout:    Option<String>,
+            exec_time: Option<f64>,
+        }
+
+        let event = serde_json::from_value::<Event>(event).ok()?;
+        if event.kind != "test" {
+            return None;
+        }
+        let status = match event.event.as_str() {
+            "ok" => Status::Passed,
+            "failed" | "timeout" => Status::Failed,
+            "ignored" => Status::Ignored,
+            _ => return None,
+        };
+        Some(Self {
+            name: event.name,
+            status,
+            output: event.stdout,
+            exec_time: event.exec_time,
+        })
+    }
+
+    /// Parse the results from libtest's plain output, along with what the
+    /// failed tests printed
+    pub fn parse_plain<'a>(lines: impl IntoIterator<Item = &'a str>) -> Vec<Self> {
+        let mut results = Vec::new();
+        let mut outputs = Vec::<(String, String)>::new();
+        // The test whose output is being read from the failures section
+        let mut reading = None;
+        for line in lines {
+            if let Some(header) = line.strip_prefix("---- ")
+                && let Some(name) = header.strip_suffix(" stdout ----")
+            {
+                outputs.push((name.to_string(), String::new()));
+                reading = Some(outputs.len() - 1);
+                continue;
+            }
+            if line == "failures:" || line.starts_with("test result: ") {
+                reading = None;
+            }
+            if let Some(i) = reading {
+                outputs[i].1.push_str(line);
+                outputs[i].1.push('\n');
+                continue;
+            }
+
+            let Some((name, status)) = line
+                .strip_prefix("test ")
+                .and_then(|rest| rest.split_once(" ... "))
+            else {
+                continue;
+            };
+            let status = match status {
+                "ok" => Status::Passed,
+                "FAILED" => Status::Failed,
+                _ if status.starts_with("ignored") => Status::Ignored,
+                _ => continue,
+            };
+            results.push(Self {
+                name: name.to_string(),
+                status,
+                output: None,
+                exec_time: None,
+            });
+        }
+        for (name, output) in outputs {
+            if let Some(result) = results
+                .iter_mut()
+                .find(|r| r.name == name && r.status == Status::Failed)
+            {
+                result.output = Some(output.trim_end().to_string());
+            }
+        }
+        results
+    }
+
+    /// The name as understood by libtest's filters. nextest prefixes it with
+    /// the test binary.
+    fn filter(&self) -> &str {
+        self.name
+            .rsplit_once('$')
+            .map_or(self.name.as_str(), |(_, name)| name)
+    }
+}
+
+/// Keeps track of failing tests across runs
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Names of the tests that failed in the latest run
+    failing:   Vec<String>,
+    /// Whether the current run only includes the previously failing tests
+    rerunning: bool,
+    /// Whether the toolchain is a nightly one, once it's known
+    nightly:   Option<bool>,
+}
+
+impl Session {
+    /// The test command for the next run
+    pub fn command(&mut self, config: &Test) -> Command {
+        self.rerunning = config.failed_first && !self.failing.is_empty();
+        let (args, test_args) = match config.args.iter().position(|a| a == "--") {
+            Some(i) => (&config.args[..i], &config.args[i + 1..]),
+            None => (&config.args[..], &[][..]),
+        };
+
+        let mut cmd = Command::new("cargo");
+        match config.runner {
+            TestRunner::Cargo => {
+                cmd.args(["test", "--message-format", "json"])
+                    .args(args)
+                    .arg("--");
+                if config.unstable_json {
+                    // libtest only accepts `-Z` on nightly otherwise
+                    cmd.env("RUSTC_BOOTSTRAP", "1");
+                }
+                if config.unstable_json || self.nightly() {
+                    cmd.args(["-Z", "unstable-options", "--format", "json"]);
+                }
+            }
+            TestRunner::Nextest => {
+                cmd.env("NEXTEST_EXPERIMENTAL_LIBTEST_JSON", "1")
+                    .args(["nextest", "run", "--no-fail-fast"])
+                    .args(["--cargo-message-format", "json"])
+                    .args(["--message-format", "libtest-json"])
+                    .args(args)
+                    .arg("--");
+            }
+        }
+        cmd.args(test_args);
+        if self.rerunning {
+            log!("Rerunning {} failing tests first", self.failing.len());
+            cmd.arg("--exact").args(&self.failing);
+        }
+        cmd
+    }
+
+    /// Whether `rustc` is a nightly toolchain, which lets libtest report its
+    /// results as JSON
+    fn nightly(&mut self) -> bool {
+        *self.nightly.get_or_insert_with(|| {
+            Command::new("rustc")
+                .arg("--version")
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .ok()
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .is_some_and(|version| {
+                    version.contains("-nightly") || version.contains("-dev")
+                })
+        })
+    }
+
+    /// Remember the failures of a finished run that succeeded if `ok`, and
+    /// return whether all tests should be run right away, because the
+    /// previously failing ones pass now
+    pub fn finish(&mut self, results: &[TestResult], ok: bool) -> bool {
+        // Without any results, the tests didn't even compile, unless the
+        // failing tests are gone
+        if results.is_empty() && !ok {
+            return false;
+        }
+        self.failing = results
+            .iter()
+            .filter(|r| r.status == Status::Failed)
+            .map(|r| r.filter().to_string())
+            .collect();
+        self.failing.sort();
+        self.failing.dedup();
+        self.rerunning && self.failing.is_empty()
+    }
+}
+
+/// Render the output of the failed tests followed by a summary line
+pub fn report(results: &[TestResult], duration: Duration) -> String {
+    let color = std::io::stderr().is_terminal();
+    let count = |status: Status| results.iter().filter(|r| r.status == status).count();
+
+    let mut out = String::new();
+    for result in results.iter().filter(|r| r.status == Status::Failed) {
+        let time = result
+            .exec_time
+            .map(|t| format!(" ({t:.2}s)"))
+            .unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{} {}{time}",
+            paint(color, "1;31", "FAIL"),
+            result.name
+        );
+        if let Some(output) = result.output.as_deref().filter(|o| !o.trim().is_empty()) {
+            for line in output.trim_end().lines() {
+                let _ = writeln!(out, "    {line}");
+            }
+        }
+    }
+
+    let failed = count(Status::Failed);
+    let status = match failed {
+        0 => paint(color, "1;32", "Tests passed"),
+        _ => paint(color, "1;31", "Tests failed"),
+    };
+    let _ = writeln!(
+        out,
+        "{status}: {} passed, {failed} failed, {} ignored in {:.2}s",
+        count(Status::Passed),
+        count(Status::Ignored),
+        duration.as_secs_f64()
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result(name: &str, event: &str) -> TestResult {
+        TestResult::parse(json!({ "type": "test", "event": event, "name": name }))
+            .unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let failed = TestResult::parse(json!({
+            "type": "test",
+            "event": "failed",
+            "name": "tests::parse",
+            "stdout": "assertion failed",
+            "exec_time": 0.25,
+        }))
+        .unwrap();
+        assert_eq!(failed.status, Status::Failed);
+        assert_eq!(failed.output.as_deref(), Some("assertion failed"));
+        assert_eq!(failed.exec_time, Some(0.25));
+
+        assert_eq!(result("a", "ok").status, Status::Passed);
+        assert_eq!(result("a", "timeout").status, Status::Failed);
+        assert_eq!(result("a", "ignored").status, Status::Ignored);
+
+        assert!(
+            TestResult::parse(json!({ "type": "test", "event": "started", "name": "a" }))
+                .is_none()
+        );
+        assert!(
+            TestResult::parse(json!({ "type": "suite", "event": "ok", "passed": 3 }))
+                .is_none()
+        );
+        assert!(TestResult::parse(json!({ "reason": "compiler-artifact" })).is_none());
+    }
+
+    #[test]
+    fn parse_plain() {
+        let output = "\
+running 3 tests
+test tests::a ... ok
+test tests::b ... FAILED
+test tests::c ... ignored, slow
+
+failures:
+
+---- tests::b stdout ----
+thread 'tests::b' panicked at src/lib.rs:3:5:
+assertion failed
+
+
+failures:
+    tests::b
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out";
+        let results = TestResult::parse_plain(output.lines());
+        let statuses = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                ("tests::a", Status::Passed),
+                ("tests::b", Status::Failed),
+                ("tests::c", Status::Ignored),
+            ]
+        );
+        assert_eq!(
+            results[1].output.as_deref(),
+            Some("thread 'tests::b' panicked at src/lib.rs:3:5:\nassertion failed")
+        );
+        assert_eq!(results[0].output, None);
+    }
+
+    #[test]
+    fn unstable_json() {
+        let json = |session: &mut Session, config: &Test| {
+            let cmd = session.command(config);
+            let bootstrap = cmd
+                .get_envs()
+                .any(|(key, value)| key == "RUSTC_BOOTSTRAP" && value.is_some());
+            (cmd.get_args().any(|a| a == "unstable-options"), bootstrap)
+        };
+        let mut stable = Session {
+            nightly: Some(false),
+            ..Session::default()
+        };
+        let mut nightly = Session {
+            nightly: Some(true),
+            ..Session::default()
+        };
+        let config = Test::default();
+        assert_eq!(json(&mut stable, &config), (false, false));
+        assert_eq!(json(&mut nightly, &config), (true, false));
+
+        let config = Test {
+            unstable_json: true,
+            ..Test::default()
+        };
+        assert_eq!(json(&mut stable, &config), (true, true));
+    }
+
+    #[test]
+    fn filter() {
+        assert_eq!(result("tests::parse", "ok").filter(), "tests::parse");
+        assert_eq!(
+            result("watchf::bin/watchf$tests::parse", "ok").filter(),
+            "tests::parse"
+        );
+    }
+
+    #[test]
+    fn failed_first() {
+        let config = Test::default();
+        let args = |cmd: &Command| {
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        let mut session = Session::default();
+
+        let cmd = session.command(&config);
+        assert!(!args(&cmd).contains(&"--exact".to_string()));
+        assert!(!session.finish(
+            &[
+                result("b", "failed"),
+                result("a", "ok"),
+                result("bin$a", "failed"),
+                result("b", "failed"),
+            ],
+            false
+        ));
+        assert_eq!(session.failing, ["a", "b"]);
+        // Tests that didn't compile don't forget the failures
+        assert!(!session.finish(&[], false));
+        assert_eq!(session.failing, ["a", "b"]);
+
+        // Only the failing tests run, and once they pass, all tests run
+        let cmd = session.command(&config);
+        assert!(args(&cmd).ends_with(&["--exact", "a", "b"].map(String::from)));
+        assert!(session.finish(&[result("a", "ok"), result("b", "ok")], true));
+        assert!(session.failing.is_empty());
+        let cmd = session.command(&config);
+        assert!(!args(&cmd).contains(&"--exact".to_string()));
+        assert!(!session.finish(&[result("a", "ok"), result("b", "ok")], true));
+
+        // A rerun that still fails doesn't run all tests
+        session.finish(&[result("a", "failed")], false);
+        session.command(&config);
+        assert!(!session.finish(&[result("a", "failed")], false));
+
+        // A rerun that finds none of the failing tests because they're gone
+        // runs all tests
+        let cmd = session.command(&config);
+        assert!(args(&cmd).ends_with(&["--exact", "a"].map(String::from)));
+        assert!(session.finish(&[], true));
+        assert!(session.failing.is_empty());
+        assert!(!args(&session.command(&config)).contains(&"--exact".to_string()));
+        session.finish(&[result("a", "failed")], false);
+
+        // Without `failed-first` the failures never narrow the run
+        let config = Test {
+            failed_first: false,
+            ..Test::default()
+        };
+        assert!(!args(&session.command(&config)).contains(&"--exact".to_string()));
+        assert!(!session.finish(&[result("a", "ok")], true));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    debounce::ChangeSet,
+    filter::Globs,
+    process::Signal,
+    tasks::{Graph, Kind},
+};
+use eyre::ensure;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A rule deciding what a change to some paths does, instead of running every
+/// task affected by it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Trigger {
+    /// Gitignore-style globs of the paths this rule applies to
+    pub paths:    Vec<String>,
+    /// The tasks to run. Only these run, not the tasks they depend on.
+    #[serde(default)]
+    pub tasks:    Vec<String>,
+    /// What happens to the running services once the tasks succeeded
+    #[serde(default)]
+    pub service:  ServiceAction,
+    /// The signal sent with `service = "signal"`
+    #[serde(default = "default_signal")]
+    pub signal:   Signal,
+    /// The services `service` applies to, all of them if empty
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+pub fn default_signal() -> Signal {
+    Signal::HUP
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceAction {
+    /// Restart the program
+    #[default]
+    Restart,
+    /// Send `signal` to the program, e.g. to make it reload its config
+    Signal,
+    /// Leave the program alone
+    Keep,
+}
+
+/// A [`ServiceAction`] along with its signal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Restart,
+    Signal(Signal),
+    Keep,
+}
+
+impl Action {
+    pub fn new(action: ServiceAction, signal: Signal) -> Self {
+        match action {
+            ServiceAction::Restart => Self::Restart,
+            ServiceAction::Signal => Self::Signal(signal),
+            ServiceAction::Keep => Self::Keep,
+        }
+    }
+
+    /// Combine two actions for the same service, restarting takes precedence
+    /// over signalling
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Restart, _) | (_, Self::Restart) => Self::Restart,
+            (Self::Signal(signal), _) | (_, Self::Signal(signal)) => Self::Signal(signal),
+            (Self::Keep, Self::Keep) => Self::Keep,
+        }
+    }
+}
+
+/// The trigger rules with their globs compiled
+pub struct Triggers(Vec<(Globs, Trigger)>);
+
+/// What a batch of changes does according to the trigger rules
+#[derive(Debug, Default)]
+pub struct Route {
+    /// The tasks the matching rules run
+    pub tasks:     BTreeSet<String>,
+    /// What the matching rules do to each service
+    pub services:  BTreeMap<String, Action>,
+    /// The tasks each service waits for before the action is taken
+    pub after:     BTreeMap<String, BTreeSet<String>>,
+    /// The paths no rule applies to, which run every task affected by them
+    pub unmatched: ChangeSet,
+}
+
+impl Triggers {
+    pub fn new(triggers: &[Trigger], graph: &Graph) -> eyre::Result<Self> {
+        let mut rules = Vec::new();
+        for trigger in triggers {
+            for task in &trigger.tasks {
+                ensure!(
+                    graph.contains(task),
+                    "Trigger for {:?} runs unknown task {task:?}",
+                    trigger.paths
+                );
+                ensure!(
+                    graph.task(task).kind != Kind::Service,
+                    "Trigger for {:?} runs service {task:?}, list it in `services` instead",
+                    trigger.paths
+                );
+            }
+            for service in &trigger.services {
+                ensure!(
+                    graph.contains(service) && graph.task(service).kind == Kind::Service,
+                    "Trigger for {:?} applies to unknown service {service:?}",
+                    trigger.paths
+                );
+            }
+            rules.push((Globs::new(&trigger.paths)?, trigger.clone()));
+        }
+        Ok(Self(rules))
+    }
+
+    /// The tasks the rules run
+    pub fn tasks(&self) -> impl Iterator<Item = &str> {
+        self.0
+            .iter()
+            .flat_map(|(_, trigger)| trigger.tasks.iter().map(String::as_str))
+    }
+
+    /// Apply the first matching rule to every path in `changes`. Rules only
+    /// apply to the running `services`.
+    pub fn route(&self, changes: &ChangeSet, services: &[String]) -> Route {
+        let mut route = Route::default();
+        for path in changes.paths() {
+            let Some((_, trigger)) = self.0.iter().find(|(globs, _)| globs.matches(path))
+            else {
+                route.unmatched.insert(path.to_path_buf());
+                continue;
+            };
+            route.tasks.extend(trigger.tasks.iter().cloned());
+
+            let action = Action::new(trigger.service, trigger.signal);
+            let applies = services
+                .iter()
+                .filter(|s| trigger.services.is_empty() || trigger.services.contains(s));
+            for service in applies {
+                route
+                    .services
+                    .entry(service.clone())
+                    .and_modify(|a| *a = a.or(action))
+                    .or_insert(action);
+                if action != Action::Keep {
+                    route
+                        .after
+                        .entry(service.clone())
+                        .or_default()
+                        .extend(trigger.tasks.iter().cloned());
+                }
+            }
+        }
+        route
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tasks::Task;
+    use std::path::{Path, PathBuf};
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn graph() -> Graph {
+        let task = |kind: Kind| Task {
+            cmd: strings(&["true"]),
+            kind,
+            ..Task::default()
+        };
+        let tasks = [
+            ("migrate", task(Kind::Command)),
+            ("bundle", task(Kind::Command)),
+            ("api", task(Kind::Service)),
+            ("web", task(Kind::Service)),
+        ];
+        Graph::of(tasks).unwrap()
+    }
+
+    fn triggers() -> Triggers {
+        let rules = [
+            Trigger {
+                paths:    strings(&["migrations/**"]),
+                tasks:    strings(&["migrate"]),
+                service:  ServiceAction::Keep,
+                signal:   default_signal(),
+                services: Vec::new(),
+            },
+            Trigger {
+                paths:    strings(&["assets/**", "*.sql"]),
+                tasks:    strings(&["bundle"]),
+                service:  ServiceAction::Signal,
+                signal:   Signal::HUP,
+                services: strings(&["web"]),
+            },
+        ];
+        Triggers::new(&rules, &graph()).unwrap()
+    }
+
+    fn changes(paths: &[&str]) -> ChangeSet {
+        let mut changes = ChangeSet::default();
+        for path in paths {
+            changes.insert(PathBuf::from(path));
+        }
+        changes
+    }
+
+    #[test]
+    fn routes_paths_to_the_first_matching_rule() {
+        let services = strings(&["api", "web"]);
+        let route = triggers().route(
+            &changes(&["migrations/1.sql", "assets/app.css", "src/main.rs"]),
+            &services,
+        );
+
+        assert_eq!(
+            route.tasks,
+            BTreeSet::from(["bundle", "migrate"].map(String::from))
+        );
+        assert_eq!(route.services["api"], Action::Keep);
+        assert_eq!(route.services["web"], Action::Signal(Signal::HUP));
+        assert_eq!(route.after.keys().collect::<Vec<_>>(), ["web"]);
+        assert_eq!(route.after["web"], BTreeSet::from(["bundle".to_string()]));
+        assert_eq!(
+            route.unmatched.paths().collect::<Vec<_>>(),
+            [Path::new("src/main.rs")]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_tasks() {
+        let graph = Graph::of([]).unwrap();
+        let rule = Trigger {
+            paths:    strings(&["*.rs"]),
+            tasks:    strings(&["build"]),
+            service:  ServiceAction::Restart,
+            signal:   default_signal(),
+            services: Vec::new(),
+        };
+        assert!(Triggers::new(&[rule], &graph).is_err());
+    }
+
+    #[test]
+    fn rejects_services_as_tasks() {
+        let rule = Trigger {
+            paths:    strings(&["web/**"]),
+            tasks:    strings(&["web"]),
+            service:  ServiceAction::Restart,
+            signal:   default_signal(),
+            services: Vec::new(),
+        };
+        assert!(Triggers::new(&[rule], &graph()).is_err());
+    }
+
+    #[test]
+    fn restarting_takes_precedence() {
+        let signal = Action::Signal(Signal::HUP);
+        assert_eq!(signal.or(Action::Restart), Action::Restart);
+        assert_eq!(Action::Keep.or(signal), signal);
+        assert_eq!(Action::Keep.or(Action::Keep), Action::Keep);
+    }
+}
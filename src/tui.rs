@@ -0,0 +1,369 @@
+use crate::{
+    Message,
+    diagnostics::Counts,
+    keys::{self, Key},
+    output,
+};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, IsTerminal},
+    sync::{
+        OnceLock,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for a key press before showing new events
+const TICK: Duration = Duration::from_millis(100);
+/// The number of lines kept per pane
+const SCROLLBACK: usize = 5000;
+/// The lines scrolled with page up and page down
+const PAGE: usize = 20;
+/// Tag colors, in the same order as in line mode
+const COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+];
+
+static SINK: OnceLock<Sender<Event>> = OnceLock::new();
+
+/// Where a line of output is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pane {
+    /// Output of watchf and the tasks
+    Tasks,
+    /// Output of the services
+    Services,
+}
+
+/// Something that happened, for the dashboard to show
+pub enum Event {
+    Line {
+        pane: Pane,
+        tag:  String,
+        text: String,
+    },
+    TaskStarted {
+        name: String,
+    },
+    TaskFinished {
+        name:     String,
+        ok:       bool,
+        duration: Duration,
+        counts:   Counts,
+    },
+    TaskCancelled {
+        name: String,
+    },
+    ServiceStarted {
+        name: String,
+        pid:  u32,
+    },
+    ServiceReady {
+        name: String,
+    },
+    ServiceStopped {
+        name:   String,
+        status: String,
+    },
+    /// The dashboard is closing
+    Close,
+}
+
+/// Show `event` on the dashboard and return whether it's open
+pub fn send(event: Event) -> bool {
+    SINK.get().is_some_and(|tx| tx.send(event).is_ok())
+}
+
+/// The full-screen dashboard, which is closed when this is dropped
+pub struct Tui {
+    handle: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Tui {
+    /// Open the dashboard if stdout is a terminal. The keys it doesn't handle
+    /// itself are sent to `tx` as [`Message::Key`].
+    pub fn spawn(tx: Sender<Message>) -> io::Result<Option<Self>> {
+        if !io::stdout().is_terminal() {
+            return Ok(None);
+        }
+        let (events_tx, events_rx) = mpsc::channel();
+        if SINK.set(events_tx).is_err() {
+            return Ok(None);
+        }
+        let terminal = ratatui::try_init()?;
+        let handle = thread::spawn(move || run(terminal, events_rx, tx));
+        Ok(Some(Self {
+            handle: Some(handle),
+        }))
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        send(Event::Close);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        ratatui::restore();
+    }
+}
+
+fn run(
+    mut terminal: DefaultTerminal,
+    rx: Receiver<Event>,
+    tx: Sender<Message>,
+) -> io::Result<()> {
+    let mut state = State::default();
+    loop {
+        terminal.draw(|frame| state.draw(frame))?;
+        if event::poll(TICK)?
+            && let event::Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            state.key(key.code, key.modifiers, &tx);
+        }
+        for event in rx.try_iter() {
+            if let Event::Close = event {
+                return Ok(());
+            }
+            state.apply(event);
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    tasks:    BTreeMap<String, TaskState>,
+    services: BTreeMap<String, ServiceState>,
+    /// The panes, indexed by [`Pane`]
+    panes:    [PaneState; 2],
+    focus:    usize,
+}
+
+enum TaskState {
+    Running(Instant),
+    Finished {
+        ok:       bool,
+        duration: Duration,
+        counts:   Counts,
+    },
+    Cancelled,
+}
+
+enum ServiceState {
+    Running {
+        pid:   u32,
+        since: Instant,
+        ready: bool,
+    },
+    Stopped(String),
+}
+
+#[derive(Default)]
+struct PaneState {
+    lines:  VecDeque<Line<'static>>,
+    /// How many lines the view is scrolled up from the bottom
+    scroll: usize,
+}
+
+impl State {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Line { pane, tag, text } => {
+                let pane = &mut self.panes[pane as usize];
+                let color = COLORS[output::color_index(&tag)];
+                pane.lines.push_back(Line::from(vec![
+                    Span::styled(format!("{tag} | "), Style::new().fg(color)),
+                    Span::raw(output::strip_ansi(&text)),
+                ]));
+                if pane.lines.len() > SCROLLBACK {
+                    pane.lines.pop_front();
+                }
+                // Keep a scrolled view where it is
+                if pane.scroll > 0 {
+                    pane.scroll += 1;
+                }
+            }
+            Event::TaskStarted { name } => {
+                self.tasks.insert(name, TaskState::Running(Instant::now()));
+            }
+            Event::TaskFinished {
+                name,
+                ok,
+                duration,
+                counts,
+            } => {
+                let state = TaskState::Finished {
+                    ok,
+                    duration,
+                    counts,
+                };
+                self.tasks.insert(name, state);
+            }
+            Event::TaskCancelled { name } => {
+                self.tasks.insert(name, TaskState::Cancelled);
+            }
+            Event::ServiceStarted { name, pid } => {
+                let state = ServiceState::Running {
+                    pid,
+                    since: Instant::now(),
+                    ready: false,
+                };
+                self.services.insert(name, state);
+            }
+            Event::ServiceReady { name } => {
+                if let Some(ServiceState::Running { ready, .. }) =
+                    self.services.get_mut(&name)
+                {
+                    *ready = true;
+                }
+            }
+            Event::ServiceStopped { name, status } => {
+                self.services.insert(name, ServiceState::Stopped(status));
+            }
+            Event::Close => {}
+        }
+    }
+
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers, tx: &Sender<Message>) {
+        let pane = &mut self.panes[self.focus];
+        let max = pane.lines.len();
+        match code {
+            // Ctrl-C doesn't send SIGINT in raw mode
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                let _ = tx.send(Message::Key(Key::Quit));
+            }
+            KeyCode::Char('c') => self.panes = Default::default(),
+            KeyCode::Tab => self.focus = (self.focus + 1) % self.panes.len(),
+            KeyCode::Up => pane.scroll = (pane.scroll + 1).min(max),
+            KeyCode::Down => pane.scroll = pane.scroll.saturating_sub(1),
+            KeyCode::PageUp => pane.scroll = (pane.scroll + PAGE).min(max),
+            KeyCode::PageDown => pane.scroll = pane.scroll.saturating_sub(PAGE),
+            KeyCode::Home => pane.scroll = max,
+            KeyCode::End => pane.scroll = 0,
+            KeyCode::Char(c) => {
+                if let Some(key) = Key::from_char(c) {
+                    let _ = tx.send(Message::Key(key));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let status = self.status();
+        let [header, tasks, services, help] = Layout::vertical([
+            Constraint::Length(status.len() as u16 + 2),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(status).block(Block::bordered().title(" watchf ")),
+            header,
+        );
+        self.draw_pane(frame, tasks, Pane::Tasks, " Tasks ");
+        self.draw_pane(frame, services, Pane::Services, " Services ");
+        frame.render_widget(
+            Paragraph::new(format!("{}, arrows scroll, tab switches pane", keys::HELP))
+                .dim(),
+            help,
+        );
+    }
+
+    /// One line with the state of every task, then one line per service
+    fn status(&self) -> Vec<Line<'static>> {
+        let mut tasks = Vec::new();
+        for (name, state) in &self.tasks {
+            let (text, color) = match state {
+                TaskState::Running(since) => (
+                    format!("running {}", uptime(since.elapsed())),
+                    Color::Yellow,
+                ),
+                TaskState::Finished {
+                    ok,
+                    duration,
+                    counts,
+                } => {
+                    let mut text = format!("{:.2}s", duration.as_secs_f64());
+                    if counts.errors > 0 || counts.warnings > 0 {
+                        text.push_str(&format!(", {counts}"));
+                    }
+                    let color = if *ok { Color::Green } else { Color::Red };
+                    (text, color)
+                }
+                TaskState::Cancelled => ("cancelled".to_string(), Color::DarkGray),
+            };
+            tasks.push(Span::raw(format!("{name} ")).bold());
+            tasks.push(Span::styled(text, Style::new().fg(color)));
+            tasks.push(Span::raw("   "));
+        }
+
+        let mut lines = vec![Line::from(tasks)];
+        for (name, state) in &self.services {
+            let state = match state {
+                ServiceState::Running { pid, since, ready } => Span::styled(
+                    format!(
+                        "{}, pid {pid}, up {}",
+                        if *ready { "ready" } else { "running" },
+                        uptime(since.elapsed())
+                    ),
+                    Style::new().fg(Color::Green),
+                ),
+                ServiceState::Stopped(status) => Span::styled(
+                    format!("stopped, {status}"),
+                    Style::new().fg(Color::Red),
+                ),
+            };
+            lines.push(Line::from(vec![
+                Span::raw(format!("{name} ")).bold(),
+                state,
+            ]));
+        }
+        lines
+    }
+
+    fn draw_pane(&self, frame: &mut Frame, area: Rect, pane: Pane, title: &str) {
+        let state = &self.panes[pane as usize];
+        let height = area.height.saturating_sub(2) as usize;
+        let bottom = state.lines.len().saturating_sub(state.scroll);
+        let top = bottom.saturating_sub(height);
+        let lines = state.lines.range(top..bottom).cloned().collect::<Vec<_>>();
+
+        let mut block = Block::bordered().title(title.to_string());
+        if state.scroll > 0 {
+            block = block.title_bottom(format!(" {} lines below ", state.scroll));
+        }
+        if self.focus == pane as usize {
+            block = block.border_style(Style::new().fg(Color::Yellow));
+        }
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+/// A duration like `1h02m`, `3m12s` or `12s`
+fn uptime(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}